@@ -0,0 +1,572 @@
+use std::fmt;
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::http::StatusCode;
+use actix_web::{
+    delete, get, post, web, FromRequest, HttpRequest, HttpResponse, ResponseError, Scope,
+};
+use bollard::container::ListContainersOptions;
+use bollard::errors::Error;
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
+use visor::config::Config;
+use visor::container::container::container_exited_duration;
+use visor::history::{self, History, Query, Record};
+use visor::instance::find_instance;
+use visor::labels::Policy;
+use visor::lease::{Leases, DEFAULT_EXTEND_DAYS};
+use visor::link;
+use visor::notify::container_name;
+use visor::plan::ActionKind;
+use visor::whitelist::{self, Entries, Kind};
+
+use crate::lease::LeaseStore;
+use crate::whitelist::WhitelistStore;
+
+/// Versioned by path, a breaking change gets a new scope served side by side.
+pub fn v1() -> Scope {
+    web::scope("/api/v1")
+        .app_data(web::JsonConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()))
+        .app_data(web::PathConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()))
+        .app_data(web::QueryConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()))
+        .service(list_containers)
+        .service(get_container)
+        .service(start_container)
+        .service(stop_container)
+        .service(extend_lease)
+        .service(get_whitelist)
+        .service(add_whitelist)
+        .service(remove_whitelist)
+        .service(get_history)
+        .default_service(web::to(|| async {
+            Err::<HttpResponse, _>(ApiError::not_found("No such endpoint"))
+        }))
+}
+
+/// Every failure answers `{"error": {"code": "...", "message": "..."}}`.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn bad_request(message: impl ToString) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    fn unauthorized(message: impl ToString) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    fn not_found(message: impl ToString) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    fn conflict(message: impl ToString) -> Self {
+        Self::new(StatusCode::CONFLICT, "conflict", message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorDetail<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: &self.message,
+            },
+        })
+    }
+}
+
+/// Docker answers 404 for unknown containers and 304 when already started or stopped.
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::DockerResponseServerError {
+                status_code: 404,
+                message,
+            } => Self::not_found(message),
+            Error::DockerResponseServerError {
+                status_code: 304, ..
+            } => Self::conflict("The container is already in the requested state"),
+            Error::DockerResponseServerError {
+                status_code: 409,
+                message,
+            } => Self::conflict(message),
+            e => Self::new(StatusCode::BAD_GATEWAY, "docker_error", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", e)
+    }
+}
+
+/// Granted to requests sending one of `serv.api_tokens` as `Authorization: Bearer <token>`.
+pub struct ApiToken;
+
+impl FromRequest for ApiToken {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, ApiError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let tokens = req
+            .app_data::<web::Data<Config>>()
+            .map(|cfg| cfg.serv.api_tokens.as_slice())
+            .unwrap_or_default();
+        ready(authorize(req, tokens))
+    }
+}
+
+fn authorize(req: &HttpRequest, tokens: &[String]) -> Result<ApiToken, ApiError> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| ApiError::unauthorized("Missing API token"))?;
+    // Every token is compared so the time taken tells nothing about them
+    let matched = tokens
+        .iter()
+        .fold(false, |matched, t| link::secret_eq(t, token) | matched);
+    if token.is_empty() || !matched {
+        return Err(ApiError::unauthorized("Invalid API token"));
+    }
+    Ok(ApiToken)
+}
+
+#[derive(Debug, Serialize)]
+struct ContainerInfo {
+    id: String,
+    name: String,
+    image: String,
+    state: String,
+    status: String,
+    /// Unix time
+    created: Option<i64>,
+    owner: Option<String>,
+    deploy_dir: Option<String>,
+    base_url: Option<String>,
+    /// Unix time the lease ends, null without an active lease
+    lease_until: Option<i64>,
+}
+
+impl ContainerInfo {
+    async fn new(
+        docker: &Docker,
+        container: &ContainerSummary,
+        cfg: &Config,
+        leases: &Leases,
+        now: i64,
+    ) -> Self {
+        let id = container.id.clone().unwrap_or_default();
+        // Stopped containers publish no ports, find_instance falls back to their bindings
+        let instance = find_instance(docker, container, &cfg.records_log)
+            .await
+            .ok();
+        let non_empty = |s: String| (!s.is_empty()).then_some(s);
+        Self {
+            lease_until: leases.active(&id, now).map(|lease| lease.until),
+            id,
+            name: container_name(container),
+            image: container.image.clone().unwrap_or_default(),
+            state: container.state.clone().unwrap_or_default(),
+            status: container.status.clone().unwrap_or_default(),
+            created: container.created,
+            owner: instance.as_ref().map(|i| i.owner.clone()),
+            deploy_dir: instance
+                .as_ref()
+                .and_then(|i| non_empty(i.deploy_dir.clone())),
+            base_url: instance.and_then(|i| non_empty(i.config.base_url)),
+        }
+    }
+}
+
+/// What visor will do with the container.
+#[derive(Debug, Serialize)]
+struct LifecycleStatus {
+    #[serde(flatten)]
+    container: ContainerInfo,
+    /// Protected by label, never stopped nor removed
+    protected: bool,
+    /// Matched by the whitelist, never stopped
+    whitelisted: bool,
+    /// How long the container is kept once exited, unit: second
+    lifecycle: u64,
+    /// Unix time the container exited, null while running
+    exited_at: Option<i64>,
+    /// Unix time visor removes the container, null while running or protected
+    remove_at: Option<i64>,
+}
+
+async fn all_containers(docker: &Docker) -> Result<Vec<ContainerSummary>, ApiError> {
+    let opts = ListContainersOptions::<String> {
+        all: true,
+        ..Default::default()
+    };
+    Ok(docker.list_containers(Some(opts)).await?)
+}
+
+/// Resolves a container by ID, short ID or name, as Docker does.
+async fn find_container(docker: &Docker, id: &str) -> Result<ContainerSummary, ApiError> {
+    let full_id = docker.inspect_container(id, None).await?.id;
+    all_containers(docker)
+        .await?
+        .into_iter()
+        .find(|c| c.id.is_some() && c.id == full_id)
+        .ok_or_else(|| ApiError::not_found(format!("No such container: {}", id)))
+}
+
+async fn lifecycle_status(
+    docker: &Docker,
+    cfg: &Config,
+    leases: &LeaseStore,
+    id: &str,
+) -> Result<LifecycleStatus, ApiError> {
+    let container = find_container(docker, id).await?;
+    let leases = leases.load()?;
+    let now = Utc::now().timestamp();
+    let info = ContainerInfo::new(docker, &container, cfg, &leases, now).await;
+
+    let policy = Policy::from_container(&container);
+    let lifecycle = policy.lifecycle_or(cfg.lifecycle.container);
+    let whitelist = whitelist::load(cfg);
+    let whitelisted = whitelist.containers_map.as_ref().is_some_and(|m| {
+        m.is_match(&info.id) || container.names.iter().flatten().any(|n| m.is_match(n))
+    });
+    let exited_at = match info.state.as_str() {
+        "exited" => Some(
+            now - container_exited_duration(docker, &container)
+                .await?
+                .as_secs() as i64,
+        ),
+        _ => None,
+    };
+    let remove_at = exited_at
+        .filter(|_| !policy.protect)
+        .map(|exited_at| leases.removal_time(&info.id, exited_at, lifecycle, now));
+
+    Ok(LifecycleStatus {
+        container: info,
+        protected: policy.protect,
+        whitelisted,
+        lifecycle: lifecycle.as_secs(),
+        exited_at,
+        remove_at,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    /// Only the containers of this owner, case-insensitive
+    owner: Option<String>,
+}
+
+#[get("/containers")]
+async fn list_containers(
+    _: ApiToken,
+    query: web::Query<ListQuery>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> Result<HttpResponse, ApiError> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let containers = all_containers(&docker).await?;
+    let leases = leases.load()?;
+    let now = Utc::now().timestamp();
+    let mut infos = Vec::new();
+    for container in containers.iter() {
+        let info = ContainerInfo::new(&docker, container, &cfg, &leases, now).await;
+        let owned = match &query.owner {
+            Some(owner) => info
+                .owner
+                .as_ref()
+                .is_some_and(|o| o.eq_ignore_ascii_case(owner)),
+            None => true,
+        };
+        if owned {
+            infos.push(info);
+        }
+    }
+    Ok(HttpResponse::Ok().json(infos))
+}
+
+#[get("/containers/{id}")]
+async fn get_container(
+    _: ApiToken,
+    id: web::Path<String>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> Result<HttpResponse, ApiError> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let status = lifecycle_status(&docker, &cfg, &leases, &id).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[post("/containers/{id}/start")]
+async fn start_container(
+    _: ApiToken,
+    id: web::Path<String>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> Result<HttpResponse, ApiError> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let res = docker.start_container::<String>(&id, None).await;
+    let record = Record::new(history::START_CONTAINER, &id, "api").result(&res);
+    history::record(&cfg.state_dir, record).await;
+    res?;
+    info!("Started container {} through the API", id);
+    let status = lifecycle_status(&docker, &cfg, &leases, &id).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[post("/containers/{id}/stop")]
+async fn stop_container(
+    _: ApiToken,
+    id: web::Path<String>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> Result<HttpResponse, ApiError> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let res = docker.stop_container(&id, None).await;
+    let record = Record::new(ActionKind::StopContainer.as_str(), &id, "api").result(&res);
+    history::record(&cfg.state_dir, record).await;
+    res?;
+    info!("Stopped container {} through the API", id);
+    let status = lifecycle_status(&docker, &cfg, &leases, &id).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ExtendBody {
+    days: Option<u64>,
+}
+
+/// The body is optional, the lease is extended by 7 days without it.
+#[post("/containers/{id}/extend")]
+async fn extend_lease(
+    _: ApiToken,
+    id: web::Path<String>,
+    body: Option<web::Json<ExtendBody>>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> Result<HttpResponse, ApiError> {
+    let days = body.and_then(|b| b.days).unwrap_or(DEFAULT_EXTEND_DAYS);
+    if days == 0 {
+        return Err(ApiError::bad_request("days must be greater than 0"));
+    }
+
+    // Leases are kept by full ID, as visor lists containers
+    let docker = Docker::connect_with_socket_defaults()?;
+    let container = find_container(&docker, &id).await?;
+    let container_id = container.id.as_deref().unwrap_or_default();
+    let res = leases.extend(container_id, days);
+    let reason = format!("api, {} days", days);
+    let record = Record::new(history::EXTEND_LEASE, container_id, &reason)
+        .name(&container_name(&container))
+        .result(&res);
+    history::record(&cfg.state_dir, record).await;
+    res?;
+    let status = lifecycle_status(&docker, &cfg, &leases, &id).await?;
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/// Entries of the config, which cannot be changed through the API, and those added through it.
+#[derive(Debug, Serialize)]
+struct WhitelistEntries {
+    config: Entries,
+    added: Entries,
+}
+
+fn whitelist_entries(cfg: &Config, store: &WhitelistStore) -> Result<WhitelistEntries, ApiError> {
+    Ok(WhitelistEntries {
+        config: Entries::from_config(&cfg.whitelist),
+        added: store.load()?,
+    })
+}
+
+#[get("/whitelist")]
+async fn get_whitelist(
+    _: ApiToken,
+    cfg: web::Data<Config>,
+    store: web::Data<WhitelistStore>,
+) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(whitelist_entries(&cfg, &store)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct WhitelistBody {
+    kind: Kind,
+    /// Exact value, glob or `re:` regex, as in the config
+    entry: String,
+}
+
+#[post("/whitelist")]
+async fn add_whitelist(
+    _: ApiToken,
+    body: web::Json<WhitelistBody>,
+    cfg: web::Data<Config>,
+    store: web::Data<WhitelistStore>,
+) -> Result<HttpResponse, ApiError> {
+    let entry = body.entry.trim();
+    if entry.is_empty() {
+        return Err(ApiError::bad_request("entry must not be empty"));
+    }
+    if Entries::from_config(&cfg.whitelist)
+        .entries(body.kind)
+        .iter()
+        .any(|e| e == entry)
+    {
+        return Err(ApiError::conflict("The entry is already in the config"));
+    }
+    if !store.add(body.kind, entry).map_err(ApiError::bad_request)? {
+        return Err(ApiError::conflict("The entry is already in the whitelist"));
+    }
+    info!(
+        "Added {} to the {} whitelist through the API",
+        entry,
+        body.kind.as_str()
+    );
+    Ok(HttpResponse::Created().json(whitelist_entries(&cfg, &store)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct KindPath {
+    kind: Kind,
+}
+
+#[derive(Debug, Deserialize)]
+struct EntryQuery {
+    entry: String,
+}
+
+/// The entry is passed as a query parameter, globs and image names may contain slashes.
+#[delete("/whitelist/{kind}")]
+async fn remove_whitelist(
+    _: ApiToken,
+    path: web::Path<KindPath>,
+    query: web::Query<EntryQuery>,
+    cfg: web::Data<Config>,
+    store: web::Data<WhitelistStore>,
+) -> Result<HttpResponse, ApiError> {
+    let kind = path.kind;
+    if !store.remove(kind, &query.entry)? {
+        let in_config = Entries::from_config(&cfg.whitelist)
+            .entries(kind)
+            .contains(&query.entry);
+        return Err(if in_config {
+            ApiError::conflict("Entries of the config cannot be removed through the API")
+        } else {
+            ApiError::not_found("No such entry in the whitelist")
+        });
+    }
+    info!(
+        "Removed {} from the {} whitelist through the API",
+        query.entry,
+        kind.as_str()
+    );
+    Ok(HttpResponse::Ok().json(whitelist_entries(&cfg, &store)?))
+}
+
+/// Actions taken by visor and visor-serv, newest first, `since` is a Unix time.
+#[get("/history")]
+async fn get_history(
+    _: ApiToken,
+    query: web::Query<Query>,
+    cfg: web::Data<Config>,
+) -> Result<HttpResponse, ApiError> {
+    let records = History::open(&cfg.state_dir)?.query(&query)?;
+    Ok(HttpResponse::Ok().json(records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_authorize() {
+        let tokens = vec!["abc".to_string()];
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer abc"))
+            .to_http_request();
+        assert!(authorize(&req, &tokens).is_ok());
+
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer def"))
+            .to_http_request();
+        assert_eq!(
+            authorize(&req, &tokens).err().unwrap().status,
+            StatusCode::UNAUTHORIZED
+        );
+        let req = TestRequest::default().to_http_request();
+        assert!(authorize(&req, &tokens).is_err());
+        // The API is disabled without tokens
+        let req = TestRequest::default()
+            .insert_header((AUTHORIZATION, "Bearer abc"))
+            .to_http_request();
+        assert!(authorize(&req, &[]).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_error_body() {
+        let e: ApiError = Error::DockerResponseServerError {
+            status_code: 404,
+            message: "No such container: abc".to_string(),
+        }
+        .into();
+        let resp = e.error_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"error":{"code":"not_found","message":"No such container: abc"}}"#
+        );
+
+        let e: ApiError = Error::DockerResponseServerError {
+            status_code: 304,
+            message: String::new(),
+        }
+        .into();
+        assert_eq!(e.status, StatusCode::CONFLICT);
+    }
+}
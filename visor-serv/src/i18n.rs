@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest};
+use visor::locale::Locale;
+
+/// Messages shown on the pages.
+#[derive(Debug, Clone, Copy)]
+pub enum Text {
+    UnsignedLink,
+    LinkExpired,
+    InvalidLink,
+    InvalidContainerId,
+    ContainerStarted,
+    StartFailed,
+    ContainerNotFound,
+    InvalidDays,
+    ExtendedUntil,
+    ExtendFailed,
+    PortalTitle,
+    SignInHint,
+    Email,
+    SendLink,
+    LinkSent,
+    SignInUnavailable,
+    SignInSubject,
+    SignInBody,
+    LinkUsed,
+    SignInFailed,
+    SignedInAs,
+    SignOut,
+    NoContainers,
+    Container,
+    Status,
+    Age,
+    Lease,
+    Usage,
+    Actions,
+    Start,
+    Stop,
+    Extend,
+    Release,
+    ReleaseConfirm,
+    ContainerStopped,
+    StopFailed,
+    ContainerReleased,
+    ReleaseFailed,
+    NotOwner,
+    ListFailed,
+    Back,
+}
+
+/// Picks the `lang` query parameter added by visor to its links, then `Accept-Language`,
+/// then the `locale` of the config.
+pub fn locale(req: &HttpRequest, default: Locale) -> Locale {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok();
+    if let Some(locale) = query.and_then(|q| q.get("lang").and_then(|s| parse(s))) {
+        return locale;
+    }
+
+    let accept = req
+        .headers()
+        .get("accept-language")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    accept
+        .split(',')
+        .map(|tag| tag.split(';').next().unwrap_or_default())
+        .find_map(parse)
+        .unwrap_or(default)
+}
+
+/// Accepts `zh`, `en` and regional tags such as `en-US`.
+fn parse(s: &str) -> Option<Locale> {
+    let s = s.trim().to_lowercase();
+    if s.starts_with("zh") {
+        Some(Locale::Zh)
+    } else if s.starts_with("en") {
+        Some(Locale::En)
+    } else {
+        None
+    }
+}
+
+pub fn text(locale: Locale, text: Text) -> &'static str {
+    match (locale, text) {
+        (Locale::Zh, Text::UnsignedLink) => "链接缺少签名，请使用通知中的链接",
+        (Locale::Zh, Text::LinkExpired) => "链接已过期",
+        (Locale::Zh, Text::InvalidLink) => "无效的链接",
+        (Locale::Zh, Text::InvalidContainerId) => "无效的容器ID",
+        (Locale::Zh, Text::ContainerStarted) => "容器已启动",
+        (Locale::Zh, Text::StartFailed) => "启动容器失败",
+        (Locale::Zh, Text::ContainerNotFound) => "容器不存在",
+        (Locale::Zh, Text::InvalidDays) => "无效的天数",
+        (Locale::Zh, Text::ExtendedUntil) => "容器保留期限已延长至",
+        (Locale::Zh, Text::ExtendFailed) => "延长保留期限失败",
+        (Locale::Zh, Text::PortalTitle) => "我的容器",
+        (Locale::Zh, Text::SignInHint) => "请输入创建容器时使用的邮箱，登录链接将发送到该邮箱",
+        (Locale::Zh, Text::Email) => "邮箱",
+        (Locale::Zh, Text::SendLink) => "发送登录链接",
+        (Locale::Zh, Text::LinkSent) => "如果该邮箱创建过容器，登录链接已发送，请查收邮件",
+        (Locale::Zh, Text::SignInUnavailable) => "未配置 email 或 serv_url，无法发送登录链接",
+        (Locale::Zh, Text::SignInSubject) => "visor 登录链接",
+        (Locale::Zh, Text::SignInBody) => "点击以下链接登录 visor，链接只能使用一次",
+        (Locale::Zh, Text::LinkUsed) => "链接已被使用，请重新获取登录链接",
+        (Locale::Zh, Text::SignInFailed) => "登录失败",
+        (Locale::Zh, Text::SignedInAs) => "当前用户",
+        (Locale::Zh, Text::SignOut) => "退出登录",
+        (Locale::Zh, Text::NoContainers) => "没有找到你创建的容器",
+        (Locale::Zh, Text::Container) => "容器",
+        (Locale::Zh, Text::Status) => "状态",
+        (Locale::Zh, Text::Age) => "创建时长",
+        (Locale::Zh, Text::Lease) => "保留期限",
+        (Locale::Zh, Text::Usage) => "资源使用",
+        (Locale::Zh, Text::Actions) => "操作",
+        (Locale::Zh, Text::Start) => "启动",
+        (Locale::Zh, Text::Stop) => "停止",
+        (Locale::Zh, Text::Extend) => "延长保留期限",
+        (Locale::Zh, Text::Release) => "释放",
+        (Locale::Zh, Text::ReleaseConfirm) => "释放后容器将被删除，确定吗？",
+        (Locale::Zh, Text::ContainerStopped) => "容器已停止",
+        (Locale::Zh, Text::StopFailed) => "停止容器失败",
+        (Locale::Zh, Text::ContainerReleased) => "容器已释放",
+        (Locale::Zh, Text::ReleaseFailed) => "释放容器失败",
+        (Locale::Zh, Text::NotOwner) => "你不是该容器的创建者",
+        (Locale::Zh, Text::ListFailed) => "获取容器列表失败",
+        (Locale::Zh, Text::Back) => "返回",
+        (Locale::En, Text::UnsignedLink) => "Unsigned link, please use the one in the notification",
+        (Locale::En, Text::LinkExpired) => "The link has expired",
+        (Locale::En, Text::InvalidLink) => "Invalid link",
+        (Locale::En, Text::InvalidContainerId) => "Invalid container ID",
+        (Locale::En, Text::ContainerStarted) => "Container started",
+        (Locale::En, Text::StartFailed) => "Failed to start the container",
+        (Locale::En, Text::ContainerNotFound) => "Container not found",
+        (Locale::En, Text::InvalidDays) => "Invalid number of days",
+        (Locale::En, Text::ExtendedUntil) => "The container is kept until",
+        (Locale::En, Text::ExtendFailed) => "Failed to extend the lease",
+        (Locale::En, Text::PortalTitle) => "My containers",
+        (Locale::En, Text::SignInHint) => {
+            "Enter the email your containers were created with, a sign-in link will be sent to it"
+        }
+        (Locale::En, Text::Email) => "Email",
+        (Locale::En, Text::SendLink) => "Send sign-in link",
+        (Locale::En, Text::LinkSent) => {
+            "If this email created any container, a sign-in link has been sent to it"
+        }
+        (Locale::En, Text::SignInUnavailable) => {
+            "Sign-in links cannot be sent without email and serv_url configured"
+        }
+        (Locale::En, Text::SignInSubject) => "visor sign-in link",
+        (Locale::En, Text::SignInBody) => {
+            "Open the following link to sign in to visor, it works only once"
+        }
+        (Locale::En, Text::LinkUsed) => "The link has already been used, please request a new one",
+        (Locale::En, Text::SignInFailed) => "Sign-in failed",
+        (Locale::En, Text::SignedInAs) => "Signed in as",
+        (Locale::En, Text::SignOut) => "Sign out",
+        (Locale::En, Text::NoContainers) => "No container created by you was found",
+        (Locale::En, Text::Container) => "Container",
+        (Locale::En, Text::Status) => "Status",
+        (Locale::En, Text::Age) => "Age",
+        (Locale::En, Text::Lease) => "Lease",
+        (Locale::En, Text::Usage) => "Usage",
+        (Locale::En, Text::Actions) => "Actions",
+        (Locale::En, Text::Start) => "Start",
+        (Locale::En, Text::Stop) => "Stop",
+        (Locale::En, Text::Extend) => "Extend lease",
+        (Locale::En, Text::Release) => "Release",
+        (Locale::En, Text::ReleaseConfirm) => "The container will be removed, continue?",
+        (Locale::En, Text::ContainerStopped) => "Container stopped",
+        (Locale::En, Text::StopFailed) => "Failed to stop the container",
+        (Locale::En, Text::ContainerReleased) => "Container released",
+        (Locale::En, Text::ReleaseFailed) => "Failed to release the container",
+        (Locale::En, Text::NotOwner) => "You did not create this container",
+        (Locale::En, Text::ListFailed) => "Failed to list the containers",
+        (Locale::En, Text::Back) => "Back",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_locale() {
+        let req = TestRequest::with_uri("/start_container/abc?lang=en")
+            .insert_header(("accept-language", "zh-CN,zh;q=0.9"))
+            .to_http_request();
+        assert_eq!(locale(&req, Locale::Zh), Locale::En);
+
+        let req = TestRequest::with_uri("/start_container/abc")
+            .insert_header(("accept-language", "fr-FR,en-US;q=0.8,zh;q=0.5"))
+            .to_http_request();
+        assert_eq!(locale(&req, Locale::Zh), Locale::En);
+
+        let req = TestRequest::with_uri("/start_container/abc?lang=xx").to_http_request();
+        assert_eq!(locale(&req, Locale::Zh), Locale::Zh);
+        assert_eq!(locale(&req, Locale::En), Locale::En);
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::Utc;
+use visor::config::Config;
+use visor::lease::Leases;
+
+/// Leases extended by owners, requests are handled concurrently so updates take a lock.
+#[derive(Debug)]
+pub struct LeaseStore {
+    state_dir: String,
+    max_days: u64,
+    lock: Mutex<()>,
+}
+
+impl LeaseStore {
+    /// Shares `state_dir` with visor, which reads the leases.
+    pub fn new(cfg: &Config) -> Self {
+        Self::with_dir(&cfg.state_dir, cfg.serv.lease_max_days)
+    }
+
+    fn with_dir(state_dir: &str, max_days: u64) -> Self {
+        Self {
+            state_dir: state_dir.to_string(),
+            max_days,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn load(&self) -> Result<Leases> {
+        Leases::load(&self.state_dir)
+    }
+
+    /// Returns the new end as Unix time, see `Leases::extend`.
+    pub fn extend(&self, container_id: &str, days: u64) -> Result<i64> {
+        let _guard = self.lock.lock().unwrap();
+        let mut leases = self.load()?;
+        let until = leases.extend(container_id, days, self.max_days, Utc::now().timestamp());
+        leases.save(&self.state_dir)?;
+        Ok(until)
+    }
+
+    pub fn release(&self, container_id: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut leases = self.load()?;
+        leases.release(container_id);
+        leases.save(&self.state_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extend() {
+        let dir = std::env::temp_dir().join("visor_serv_lease_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = LeaseStore::with_dir(&dir.to_string_lossy(), 10);
+        let now = Utc::now().timestamp();
+
+        let until = store.extend("abc", 7).unwrap();
+        assert!((until - now - 7 * 86400).abs() < 5);
+        // Extended from the current end, then capped
+        let until = store.extend("abc", 7).unwrap();
+        assert!((until - now - 10 * 86400).abs() < 5);
+        assert_eq!(
+            store.load().unwrap().active("abc", now).unwrap().until,
+            until
+        );
+
+        store.release("abc").unwrap();
+        assert!(store.load().unwrap().active("abc", now).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
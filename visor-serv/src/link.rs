@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use visor::link;
+
+pub use visor::link::{extend_target, LinkError, EXTEND, START_CONTAINER};
+
+/// Checks the links signed by visor with the secret both share.
+#[derive(Debug)]
+pub struct LinkVerifier {
+    secret: String,
+}
+
+impl LinkVerifier {
+    /// Takes `serv_secret` of the config, the links cannot be checked without it.
+    pub fn new(secret: &str) -> Result<Self> {
+        if secret.is_empty() {
+            return Err(anyhow!("serv_secret is required by visor-serv"));
+        }
+        Ok(Self {
+            secret: secret.to_string(),
+        })
+    }
+
+    /// Accepts the request when its `exp` and `sig` query parameters sign
+    /// `<action>:<target>:<exp>` and `exp` has not passed. The target is the
+    /// container ID, followed by the days for `extend_target`.
+    pub fn verify(&self, req: &HttpRequest, action: &str, target: &str) -> Result<(), LinkError> {
+        let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map_err(|_| LinkError::Invalid)?;
+        let (exp, sig) = match (query.get("exp"), query.get("sig")) {
+            (Some(exp), Some(sig)) => (exp, sig),
+            _ => return Err(LinkError::Unsigned),
+        };
+        let expires: i64 = exp.parse().map_err(|_| LinkError::Invalid)?;
+        link::verify(
+            &self.secret,
+            action,
+            target,
+            expires,
+            sig,
+            Utc::now().timestamp(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use visor::locale::Locale;
+
+    fn verify(uri: &str) -> Result<(), LinkError> {
+        let verifier = LinkVerifier::new("secret").unwrap();
+        let req = TestRequest::with_uri(uri).to_http_request();
+        verifier.verify(&req, START_CONTAINER, "abc123abc123")
+    }
+
+    #[test]
+    fn test_verify() {
+        // Same vector as visor/src/link.rs, long expired
+        let sig = "f7911d2fe25da06c568d27a22625cffec9c935b7b59178469e9afc68212d0e6d";
+        assert_eq!(
+            verify(&format!("/?exp=1651400000&sig={}", sig)),
+            Err(LinkError::Expired)
+        );
+        assert_eq!(
+            verify(&format!("/?exp=1651400001&sig={}", sig)),
+            Err(LinkError::Invalid)
+        );
+        assert_eq!(verify("/?lang=zh"), Err(LinkError::Unsigned));
+
+        let exp = Utc::now().timestamp() + 60;
+        let sig = link::sign("secret", START_CONTAINER, "abc123abc123", exp);
+        assert_eq!(
+            verify(&format!("/?lang=en&exp={}&sig={}", exp, sig)),
+            Ok(())
+        );
+        assert!(LinkVerifier::new("").is_err());
+    }
+
+    #[test]
+    fn test_verify_extend() {
+        let exp = Utc::now().timestamp() + 60;
+        let url = link::extend_url("", "secret", "abc123abc123", 7, exp, Locale::En);
+        let verify = |uri: &str, days| {
+            let verifier = LinkVerifier::new("secret").unwrap();
+            let req = TestRequest::with_uri(uri).to_http_request();
+            verifier.verify(&req, EXTEND, &extend_target("abc123abc123", days))
+        };
+        assert_eq!(verify(&url, 7), Ok(()));
+        // Raising the days breaks the signature
+        let raised = url.replace("days=7", "days=30");
+        assert_eq!(verify(&raised, 30), Err(LinkError::Invalid));
+    }
+}
@@ -1,19 +1,59 @@
+mod api;
+mod i18n;
+mod lease;
+mod link;
+mod portal;
 mod services;
+mod whitelist;
 
-use crate::services::start_container;
-use actix_web::{middleware, App, HttpServer};
+use crate::lease::LeaseStore;
+use crate::link::LinkVerifier;
+use crate::portal::UsedLinks;
+use crate::services::{extend, start_container};
+use crate::whitelist::WhitelistStore;
+use actix_web::{middleware, web, App, HttpServer};
+use clap::Parser;
+use visor::config::Config;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Config file of visor, shared for serv_secret, state_dir and the email settings
+    #[clap(short, long, value_name = "FILE", default_value_t = String::from("config.json"))]
+    config: String,
+}
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=info");
+async fn main() -> anyhow::Result<()> {
+    std::env::set_var("RUST_LOG", "actix_web=info,visor_serv=info");
     env_logger::init();
 
-    HttpServer::new(|| {
+    let args = Args::parse();
+    let cfg = Config::new(&args.config)?;
+    let leases = web::Data::new(LeaseStore::new(&cfg));
+    let verifier = web::Data::new(LinkVerifier::new(&cfg.serv_secret)?);
+    let used_links = web::Data::new(UsedLinks::new(&cfg));
+    let whitelist = web::Data::new(WhitelistStore::new(&cfg));
+    let cfg = web::Data::new(cfg);
+    HttpServer::new(move || {
         App::new()
             .wrap(middleware::Logger::default())
+            .app_data(cfg.clone())
+            .app_data(leases.clone())
+            .app_data(verifier.clone())
+            .app_data(used_links.clone())
+            .app_data(whitelist.clone())
             .service(start_container)
+            .service(extend)
+            .service(portal::portal)
+            .service(portal::sign_in)
+            .service(portal::auth)
+            .service(portal::sign_out)
+            .service(portal::container_action)
+            .service(api::v1())
     })
     .bind(("0.0.0.0", 17456))?
     .run()
-    .await
+    .await?;
+    Ok(())
 }
@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use bollard::container::{ListContainersOptions, RemoveContainerOptions};
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use chrono::{Local, TimeZone, Utc};
+use log::{info, warn};
+use serde::Deserialize;
+use visor::config::Config;
+use visor::container::container::get_container_usage;
+use visor::history::{self, Record};
+use visor::instance::find_instance;
+use visor::lease::{Leases, DEFAULT_EXTEND_DAYS};
+use visor::link;
+use visor::locale::Locale;
+use visor::notify::{container_name, parse_mailbox, Mailer};
+use visor::plan::ActionKind;
+use visor::state;
+
+use crate::i18n::{self, text, Text};
+use crate::lease::LeaseStore;
+use crate::services::forbidden;
+
+const SESSION_COOKIE: &str = "visor_session";
+/// Actions signed for the sign-in links and the sessions, with the email in place of a container ID
+const SIGN_IN: &str = "sign_in";
+const SESSION: &str = "session";
+
+pub const USED_LINKS_FILE: &str = "used_links.json";
+
+/// Sign-in links already used, by signature, until they expire. Kept in `state_dir`
+/// so a restart does not make them usable again.
+#[derive(Debug)]
+pub struct UsedLinks {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl UsedLinks {
+    pub fn new(cfg: &Config) -> Self {
+        Self::with_dir(&cfg.state_dir)
+    }
+
+    fn with_dir(state_dir: &str) -> Self {
+        Self {
+            path: Path::new(state_dir).join(USED_LINKS_FILE),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns false when the link was used before.
+    fn take(&self, sig: &str, expires: i64, now: i64) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        let mut sigs: HashMap<String, i64> = state::load(&self.path)?;
+        sigs.retain(|_, exp| *exp >= now);
+        let fresh = sigs.insert(sig.to_string(), expires).is_none();
+        state::save(&self.path, &sigs)?;
+        Ok(fresh)
+    }
+}
+
+/// `<exp>:<sig>:<email>`, the email comes last as it may contain colons.
+fn session_value(secret: &str, email: &str, expires: i64) -> String {
+    format!(
+        "{}:{}:{}",
+        expires,
+        link::sign(secret, SESSION, email, expires),
+        email
+    )
+}
+
+fn parse_session(secret: &str, value: &str, now: i64) -> Option<String> {
+    let mut parts = value.splitn(3, ':');
+    let expires: i64 = parts.next()?.parse().ok()?;
+    let sig = parts.next()?;
+    let email = parts.next()?;
+    link::verify(secret, SESSION, email, expires, sig, now).ok()?;
+    Some(email.to_string())
+}
+
+/// Email of the signed-in owner.
+fn session_email(req: &HttpRequest, cfg: &Config) -> Option<String> {
+    let cookie = req.cookie(SESSION_COOKIE)?;
+    parse_session(&cfg.serv_secret, cookie.value(), Utc::now().timestamp())
+}
+
+/// Bare address of the mailbox, lowercased like the owners are compared.
+fn normalize_email(s: &str) -> Result<String> {
+    Ok(parse_mailbox(s.trim())?.email.to_string().to_lowercase())
+}
+
+/// Percent-encodes a query value, a `+` would otherwise be read back as a space.
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Containers, running or not, whose owner resolved by visor is `email`.
+async fn owned_containers(
+    docker: &Docker,
+    cfg: &Config,
+    email: &str,
+) -> Result<Vec<ContainerSummary>> {
+    let opts = ListContainersOptions::<String> {
+        all: true,
+        ..Default::default()
+    };
+    let mut owned = Vec::new();
+    for container in docker.list_containers(Some(opts)).await? {
+        // Stopped containers publish no ports, find_instance falls back to their bindings
+        if let Ok(instance) = find_instance(docker, &container, &cfg.records_log).await {
+            if instance.owner.eq_ignore_ascii_case(email) {
+                owned.push(container);
+            }
+        }
+    }
+    Ok(owned)
+}
+
+fn page(status: StatusCode, lang: Locale, body: &str) -> HttpResponse {
+    let title = text(lang, Text::PortalTitle);
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>\n<html lang=\"{}\">\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n<h1>{}</h1>\n{}\n</body>\n</html>\n",
+            lang.as_str(),
+            title,
+            title,
+            body
+        ))
+}
+
+/// A message with a link back to the container list.
+fn message(status: StatusCode, lang: Locale, msg: &str) -> HttpResponse {
+    page(
+        status,
+        lang,
+        &format!(
+            "<p>{}</p>\n<p><a href=\"/portal\">{}</a></p>",
+            escape(msg),
+            text(lang, Text::Back)
+        ),
+    )
+}
+
+fn redirect_to_portal() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/portal"))
+        .finish()
+}
+
+fn sign_in_page(lang: Locale) -> HttpResponse {
+    page(
+        StatusCode::OK,
+        lang,
+        &format!(
+            "<p>{}</p>\n<form method=\"post\" action=\"/portal/sign_in\">\n<label>{} <input type=\"email\" name=\"email\" required></label>\n<button type=\"submit\">{}</button>\n</form>",
+            text(lang, Text::SignInHint),
+            text(lang, Text::Email),
+            text(lang, Text::SendLink)
+        ),
+    )
+}
+
+/// The containers of the signed-in owner, or the sign-in form.
+#[get("/portal")]
+async fn portal(
+    req: HttpRequest,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let email = match session_email(&req, &cfg) {
+        Some(email) => email,
+        None => return sign_in_page(lang),
+    };
+
+    let docker = Docker::connect_with_socket_defaults().unwrap();
+    let containers = match owned_containers(&docker, &cfg, &email).await {
+        Ok(containers) => containers,
+        Err(e) => {
+            let msg = format!("{}: {}", text(lang, Text::ListFailed), e);
+            return message(StatusCode::INTERNAL_SERVER_ERROR, lang, &msg);
+        }
+    };
+    let leases = leases.load().unwrap_or_else(|e| {
+        warn!("Load leases failed: {}", e);
+        Leases::default()
+    });
+
+    let mut body = format!(
+        "<form method=\"post\" action=\"/portal/sign_out\">{}: {} <button type=\"submit\">{}</button></form>\n",
+        text(lang, Text::SignedInAs),
+        escape(&email),
+        text(lang, Text::SignOut)
+    );
+    if containers.is_empty() {
+        body.push_str(&format!("<p>{}</p>", text(lang, Text::NoContainers)));
+        return page(StatusCode::OK, lang, &body);
+    }
+
+    body.push_str(&format!(
+        "<table border=\"1\" cellpadding=\"4\">\n<tr><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>\n",
+        text(lang, Text::Container),
+        text(lang, Text::Status),
+        text(lang, Text::Age),
+        text(lang, Text::Lease),
+        text(lang, Text::Usage),
+        text(lang, Text::Actions)
+    ));
+    let now = Utc::now().timestamp();
+    for container in containers {
+        body.push_str(&row(&docker, lang, &container, &leases, now).await);
+    }
+    body.push_str("</table>");
+    page(StatusCode::OK, lang, &body)
+}
+
+async fn row(
+    docker: &Docker,
+    lang: Locale,
+    container: &ContainerSummary,
+    leases: &Leases,
+    now: i64,
+) -> String {
+    let id = container.id.clone().unwrap_or_default();
+    let running = container.state.as_deref() == Some("running");
+    let age = container
+        .created
+        .map(|created| lang.format_duration(Duration::from_secs((now - created).max(0) as u64)))
+        .unwrap_or_default();
+    let lease = match leases.active(&id, now) {
+        Some(lease) => Local
+            .timestamp_opt(lease.until, 0)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        None => "-".to_string(),
+    };
+    let usage = if running {
+        match get_container_usage(docker, &id).await {
+            Ok(usage) => format!("CPU {:.1}%, {} MiB", usage.cpu, usage.mem / 1024 / 1024),
+            Err(e) => {
+                warn!("Get usage of container {} failed: {}", id, e);
+                "-".to_string()
+            }
+        }
+    } else {
+        "-".to_string()
+    };
+
+    let button = |action: &str, label: Text, confirm: Option<Text>| {
+        let onsubmit = confirm
+            .map(|t| format!(" onsubmit=\"return confirm('{}')\"", text(lang, t)))
+            .unwrap_or_default();
+        format!(
+            "<form method=\"post\" action=\"/portal/containers/{}/{}\" style=\"display:inline\"{}><button type=\"submit\">{}</button></form>",
+            id,
+            action,
+            onsubmit,
+            text(lang, label)
+        )
+    };
+    let actions = [
+        if running {
+            button("stop", Text::Stop, None)
+        } else {
+            button("start", Text::Start, None)
+        },
+        button("extend", Text::Extend, None),
+        button("release", Text::Release, Some(Text::ReleaseConfirm)),
+    ]
+    .join(" ");
+
+    let mut short_id = id.clone();
+    short_id.truncate(12);
+    format!(
+        "<tr><td>{}<br><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+        escape(&container_name(container)),
+        short_id,
+        escape(container.status.as_deref().unwrap_or_default()),
+        age,
+        lease,
+        usage,
+        actions
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct SignInForm {
+    email: String,
+}
+
+/// Mails a one-time sign-in link to the owner. The answer is the same whether the email
+/// owns containers or not, so the page cannot be used to look up owners.
+#[post("/portal/sign_in")]
+async fn sign_in(
+    req: HttpRequest,
+    form: web::Form<SignInForm>,
+    cfg: web::Data<Config>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let email_cfg = match &cfg.email {
+        Some(email_cfg) if !cfg.serv_url.is_empty() => email_cfg,
+        _ => {
+            return message(
+                StatusCode::SERVICE_UNAVAILABLE,
+                lang,
+                text(lang, Text::SignInUnavailable),
+            )
+        }
+    };
+    let email = match normalize_email(&form.email) {
+        Ok(email) => email,
+        Err(e) => return message(StatusCode::BAD_REQUEST, lang, &e.to_string()),
+    };
+
+    let docker = Docker::connect_with_socket_defaults().unwrap();
+    match owned_containers(&docker, &cfg, &email).await {
+        Ok(containers) if containers.is_empty() => {
+            info!("Ignored: {} owns no container", email);
+        }
+        Ok(_) => {
+            if let Err(e) = send_sign_in_link(&cfg, email_cfg, lang, &email).await {
+                warn!("Send sign-in link to {} failed: {}", email, e);
+            }
+        }
+        Err(e) => warn!("List containers failed: {}", e),
+    }
+    message(StatusCode::OK, lang, text(lang, Text::LinkSent))
+}
+
+async fn send_sign_in_link(
+    cfg: &Config,
+    email_cfg: &visor::config::Email,
+    lang: Locale,
+    email: &str,
+) -> Result<()> {
+    let expires = Utc::now().timestamp() + cfg.serv.login_ttl as i64;
+    let url = format!(
+        "{}/portal/auth?lang={}&email={}&exp={}&sig={}",
+        cfg.serv_url,
+        lang.as_str(),
+        encode(email),
+        expires,
+        link::sign(&cfg.serv_secret, SIGN_IN, email, expires)
+    );
+    let body = text(lang, Text::SignInBody);
+    let mailer = Mailer::new(email_cfg)?;
+    mailer
+        .send(
+            &[parse_mailbox(email)?],
+            &[],
+            text(lang, Text::SignInSubject),
+            format!("{}:\n{}", body, url),
+            format!("<p>{}:</p>\n<p><a href=\"{}\">{}</a></p>", body, url, url),
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthQuery {
+    email: String,
+    exp: i64,
+    sig: String,
+}
+
+/// Opens a session from a sign-in link, each link works once.
+#[get("/portal/auth")]
+async fn auth(
+    req: HttpRequest,
+    query: web::Query<AuthQuery>,
+    cfg: web::Data<Config>,
+    used: web::Data<UsedLinks>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let now = Utc::now().timestamp();
+    if let Err(e) = link::verify(
+        &cfg.serv_secret,
+        SIGN_IN,
+        &query.email,
+        query.exp,
+        &query.sig,
+        now,
+    ) {
+        return forbidden(lang, e);
+    }
+    match used.take(&query.sig, query.exp, now) {
+        Ok(true) => {}
+        Ok(false) => return message(StatusCode::FORBIDDEN, lang, text(lang, Text::LinkUsed)),
+        Err(e) => {
+            warn!("Record used sign-in link failed: {}", e);
+            let msg = format!("{}: {}", text(lang, Text::SignInFailed), e);
+            return message(StatusCode::INTERNAL_SERVER_ERROR, lang, &msg);
+        }
+    }
+
+    let expires = now + cfg.serv.session_ttl as i64;
+    let cookie = Cookie::build(
+        SESSION_COOKIE,
+        session_value(&cfg.serv_secret, &query.email, expires),
+    )
+    .path("/portal")
+    .http_only(true)
+    .secure(cfg.serv_url.starts_with("https://"))
+    .same_site(SameSite::Lax)
+    .max_age(actix_web::cookie::time::Duration::seconds(
+        cfg.serv.session_ttl as i64,
+    ))
+    .finish();
+    info!("{} signed in", query.email);
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/portal"))
+        .cookie(cookie)
+        .finish()
+}
+
+#[post("/portal/sign_out")]
+async fn sign_out() -> HttpResponse {
+    let mut resp = redirect_to_portal();
+    let cookie = Cookie::build(SESSION_COOKIE, "").path("/portal").finish();
+    resp.add_removal_cookie(&cookie).unwrap();
+    resp
+}
+
+#[derive(Debug, Deserialize)]
+struct ActionForm {
+    days: Option<u64>,
+}
+
+/// Starts, stops, extends the lease of or releases a container of the signed-in owner.
+#[post("/portal/containers/{container_id}/{action}")]
+async fn container_action(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    form: web::Form<ActionForm>,
+    cfg: web::Data<Config>,
+    leases: web::Data<LeaseStore>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let email = match session_email(&req, &cfg) {
+        Some(email) => email,
+        None => return redirect_to_portal(),
+    };
+    let (container_id, action) = path.into_inner();
+
+    let docker = Docker::connect_with_socket_defaults().unwrap();
+    let owned = match owned_containers(&docker, &cfg, &email).await {
+        Ok(containers) => containers
+            .iter()
+            .any(|c| c.id.as_deref() == Some(container_id.as_str())),
+        Err(e) => {
+            let msg = format!("{}: {}", text(lang, Text::ListFailed), e);
+            return message(StatusCode::INTERNAL_SERVER_ERROR, lang, &msg);
+        }
+    };
+    if !owned {
+        return message(StatusCode::FORBIDDEN, lang, text(lang, Text::NotOwner));
+    }
+
+    info!("{} {} container {}", email, action, container_id);
+    let kind = match action.as_str() {
+        "start" => history::START_CONTAINER,
+        "stop" => ActionKind::StopContainer.as_str(),
+        "extend" => history::EXTEND_LEASE,
+        "release" => history::RELEASE_CONTAINER,
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let (ok, failed, result) = match action.as_str() {
+        "start" => (
+            Text::ContainerStarted,
+            Text::StartFailed,
+            docker
+                .start_container::<String>(&container_id, None)
+                .await
+                .map_err(|e| anyhow!("{}", e)),
+        ),
+        "stop" => (
+            Text::ContainerStopped,
+            Text::StopFailed,
+            docker
+                .stop_container(&container_id, None)
+                .await
+                .map_err(|e| anyhow!("{}", e)),
+        ),
+        "extend" => {
+            let days = form.days.unwrap_or(DEFAULT_EXTEND_DAYS);
+            if days == 0 {
+                return message(StatusCode::BAD_REQUEST, lang, text(lang, Text::InvalidDays));
+            }
+            let res = leases.extend(&container_id, days);
+            let reason = format!("portal, {} days", days);
+            let record = Record::new(kind, &container_id, &reason)
+                .owner(Some(email))
+                .result(&res);
+            history::record(&cfg.state_dir, record).await;
+            return match res {
+                Ok(until) => {
+                    let until = Local.timestamp_opt(until, 0).unwrap();
+                    let msg = format!(
+                        "{} {}",
+                        text(lang, Text::ExtendedUntil),
+                        until.format("%Y-%m-%d %H:%M")
+                    );
+                    message(StatusCode::OK, lang, &msg)
+                }
+                Err(e) => {
+                    let msg = format!("{}: {}", text(lang, Text::ExtendFailed), e);
+                    message(StatusCode::INTERNAL_SERVER_ERROR, lang, &msg)
+                }
+            };
+        }
+        "release" => (
+            Text::ContainerReleased,
+            Text::ReleaseFailed,
+            release(&docker, &leases, &container_id).await,
+        ),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    let record = Record::new(kind, &container_id, "portal")
+        .owner(Some(email))
+        .result(&result);
+    history::record(&cfg.state_dir, record).await;
+    match result {
+        Ok(_) => message(StatusCode::OK, lang, text(lang, ok)),
+        Err(e) => {
+            let msg = format!("{}: {}", text(lang, failed), e);
+            message(StatusCode::INTERNAL_SERVER_ERROR, lang, &msg)
+        }
+    }
+}
+
+/// Removes the container right away instead of waiting for visor, and drops its lease.
+async fn release(docker: &Docker, leases: &LeaseStore, container_id: &str) -> Result<()> {
+    let opts = RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    };
+    docker.remove_container(container_id, Some(opts)).await?;
+    leases.release(container_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session() {
+        let now = Utc::now().timestamp();
+        let value = session_value("secret", "a@b.com", now + 60);
+        assert_eq!(
+            parse_session("secret", &value, now).as_deref(),
+            Some("a@b.com")
+        );
+        assert_eq!(parse_session("secret", &value, now + 61), None);
+        assert_eq!(parse_session("other", &value, now), None);
+        // The email is part of the signature
+        let forged = value.replace("a@b.com", "c@d.com");
+        assert_eq!(parse_session("secret", &forged, now), None);
+        assert_eq!(parse_session("secret", "garbage", now), None);
+    }
+
+    #[test]
+    fn test_used_links() {
+        let dir = std::env::temp_dir().join("visor_serv_used_links");
+        let _ = std::fs::remove_dir_all(&dir);
+        let state_dir = dir.to_string_lossy();
+        let used = UsedLinks::with_dir(&state_dir);
+        assert!(used.take("abc", 2000, 1000).unwrap());
+        assert!(!used.take("abc", 2000, 1500).unwrap());
+        // Still used after a restart
+        let used = UsedLinks::with_dir(&state_dir);
+        assert!(!used.take("abc", 2000, 1500).unwrap());
+        // Forgotten once expired, the signature is rejected as expired by then
+        assert!(used.take("def", 3000, 2001).unwrap());
+        let sigs: HashMap<String, i64> = state::load(&dir.join(USED_LINKS_FILE)).unwrap();
+        assert_eq!(sigs, HashMap::from([("def".to_string(), 3000)]));
+        assert!(used.take("abc", 2000, 2001).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("a+b@c.com"), "a%2Bb@c.com");
+        assert_eq!(normalize_email(" A+b@C.com ").unwrap(), "a+b@c.com");
+        assert!(normalize_email("not an email").is_err());
+    }
+}
@@ -1,37 +1,123 @@
 use actix_web::{get, web, HttpRequest, HttpResponse};
 use bollard::errors::Error;
 use bollard::Docker;
+use chrono::{Local, TimeZone};
+use serde::Deserialize;
+use visor::config::Config;
+use visor::history::{self, Record};
+use visor::lease::DEFAULT_EXTEND_DAYS;
+use visor::locale::Locale;
+
+use crate::i18n::{self, text, Text};
+use crate::lease::LeaseStore;
+use crate::link::{extend_target, LinkError, LinkVerifier, EXTEND, START_CONTAINER};
 
 #[get("/start_container/{container_id}")]
-async fn start_container(req: HttpRequest, container_id: web::Path<String>) -> HttpResponse {
-    if let Some(ua) = req.headers().get("user-agent") {
-        let s = ua.to_str().unwrap_or_default();
-        if !s.to_lowercase().contains("micromessenger") {
-            return html(String::from("请通过微信浏览器打开"));
-        }
+async fn start_container(
+    req: HttpRequest,
+    container_id: web::Path<String>,
+    verifier: web::Data<LinkVerifier>,
+    cfg: web::Data<Config>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let container_id = container_id.as_str();
+    if let Err(e) = verifier.verify(&req, START_CONTAINER, container_id) {
+        return forbidden(lang, e);
     }
 
     let docker = Docker::connect_with_socket_defaults().unwrap();
-    let container_id = container_id.as_str();
     if container_id.len().lt(&12usize) {
-        return html(String::from("无效的容器ID"));
+        return html(String::from(text(lang, Text::InvalidContainerId)));
     }
 
-    match docker.start_container::<String>(container_id, None).await {
-        Ok(_) => html(String::from("容器已启动")),
+    let res = docker.start_container::<String>(container_id, None).await;
+    let record = Record::new(history::START_CONTAINER, container_id, "restart link").result(&res);
+    history::record(&cfg.state_dir, record).await;
+    match res {
+        Ok(_) => html(String::from(text(lang, Text::ContainerStarted))),
         Err(e) => match e {
             Error::DockerResponseServerError {
                 status_code,
                 message,
             } => {
-                return html(format!("启动容器失败：{} {}", status_code, message));
+                return html(format!(
+                    "{}: {} {}",
+                    text(lang, Text::StartFailed),
+                    status_code,
+                    message
+                ));
             }
-            _ => html(format!("容器启动失败: {}", e)),
+            _ => html(format!("{}: {}", text(lang, Text::StartFailed), e)),
         },
     }
 }
 
-fn html(content: String) -> HttpResponse {
+#[derive(Debug, Deserialize)]
+struct ExtendQuery {
+    days: Option<u64>,
+}
+
+/// Keeps a running or exited container from being stopped or removed by visor for `days`.
+#[get("/extend/{container_id}")]
+async fn extend(
+    req: HttpRequest,
+    container_id: web::Path<String>,
+    query: web::Query<ExtendQuery>,
+    leases: web::Data<LeaseStore>,
+    verifier: web::Data<LinkVerifier>,
+    cfg: web::Data<Config>,
+) -> HttpResponse {
+    let lang = i18n::locale(&req, cfg.locale);
+    let container_id = container_id.as_str();
+    let days = query.days.unwrap_or(DEFAULT_EXTEND_DAYS);
+    let target = extend_target(container_id, days);
+    if let Err(e) = verifier.verify(&req, EXTEND, &target) {
+        return forbidden(lang, e);
+    }
+
+    if container_id.len() < 12 {
+        return html(String::from(text(lang, Text::InvalidContainerId)));
+    }
+    if days == 0 {
+        return html(String::from(text(lang, Text::InvalidDays)));
+    }
+
+    // Leases are kept by full ID, as visor lists containers
+    let docker = Docker::connect_with_socket_defaults().unwrap();
+    let container_id = match docker.inspect_container(container_id, None).await {
+        Ok(res) => res.id.unwrap_or_default(),
+        Err(e) => return html(format!("{}: {}", text(lang, Text::ContainerNotFound), e)),
+    };
+
+    let res = leases.extend(&container_id, days);
+    let reason = format!("keep link, {} days", days);
+    let record = Record::new(history::EXTEND_LEASE, &container_id, &reason).result(&res);
+    history::record(&cfg.state_dir, record).await;
+    match res {
+        Ok(until) => {
+            let until = Local.timestamp_opt(until, 0).unwrap();
+            html(format!(
+                "{} {}",
+                text(lang, Text::ExtendedUntil),
+                until.format("%Y-%m-%d %H:%M")
+            ))
+        }
+        Err(e) => html(format!("{}: {}", text(lang, Text::ExtendFailed), e)),
+    }
+}
+
+pub fn forbidden(lang: Locale, e: LinkError) -> HttpResponse {
+    let text = match e {
+        LinkError::Unsigned => Text::UnsignedLink,
+        LinkError::Expired => Text::LinkExpired,
+        LinkError::Invalid => Text::InvalidLink,
+    };
+    HttpResponse::Forbidden()
+        .content_type("text/html; charset=utf-8")
+        .body(i18n::text(lang, text))
+}
+
+pub fn html(content: String) -> HttpResponse {
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(content)
@@ -0,0 +1,73 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+use visor::config::Config;
+use visor::whitelist::{Entries, Kind};
+
+/// Whitelist entries added through the API, requests are handled concurrently so updates
+/// take a lock. visor merges them into the whitelist of the config on every run.
+#[derive(Debug)]
+pub struct WhitelistStore {
+    state_dir: String,
+    lock: Mutex<()>,
+}
+
+impl WhitelistStore {
+    pub fn new(cfg: &Config) -> Self {
+        Self::with_dir(&cfg.state_dir)
+    }
+
+    fn with_dir(state_dir: &str) -> Self {
+        Self {
+            state_dir: state_dir.to_string(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn load(&self) -> Result<Entries> {
+        Entries::load(&self.state_dir)
+    }
+
+    /// Returns false when the entry was already added.
+    pub fn add(&self, kind: Kind, entry: &str) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = self.load()?;
+        if !entries.add(kind, entry)? {
+            return Ok(false);
+        }
+        entries.save(&self.state_dir)?;
+        Ok(true)
+    }
+
+    /// Returns false when the entry was not added through the API.
+    pub fn remove(&self, kind: Kind, entry: &str) -> Result<bool> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entries = self.load()?;
+        if !entries.remove(kind, entry) {
+            return Ok(false);
+        }
+        entries.save(&self.state_dir)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove() {
+        let dir = std::env::temp_dir().join("visor_serv_whitelist_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = WhitelistStore::with_dir(&dir.to_string_lossy());
+
+        assert!(store.add(Kind::Images, "nginx:*").unwrap());
+        assert!(!store.add(Kind::Images, "nginx:*").unwrap());
+        assert_eq!(store.load().unwrap().images, vec!["nginx:*"]);
+
+        assert!(store.remove(Kind::Images, "nginx:*").unwrap());
+        assert!(!store.remove(Kind::Images, "nginx:*").unwrap());
+        assert!(store.load().unwrap().images.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,25 +1,280 @@
-use std::{collections::{HashMap, HashSet}, fs};
+use std::{collections::HashMap, fs, str::FromStr, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use serde::Deserialize;
 
+use crate::container::victim::VictimStrategyKind;
+use crate::event::EventKind;
+use crate::locale::Locale;
+use crate::pattern::PatternSet;
+use crate::records::DEFAULT_RECORDS_LOG;
+use crate::state::DEFAULT_STATE_DIR;
+use crate::template;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// WeCom group robot webhook, leave empty to disable
+    #[serde(default)]
     pub notify_webhook: String,
+    pub slack: Option<Slack>,
+    pub dingtalk: Option<Robot>,
+    pub feishu: Option<Robot>,
+    pub email: Option<Email>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub templates: Templates,
+    /// Language of notifications and visor-serv pages, `zh` or `en`
+    #[serde(default)]
+    pub locale: Locale,
+    /// Owner email => locale, overriding `locale` for that owner
+    #[serde(default)]
+    pub owner_locales: HashMap<String, Locale>,
     pub cpu_limit: f32,
     pub mem_limit: f32,
     pub serv_url: String,
+    /// Shared with visor-serv, which reads this config too, signs the links in notifications
+    #[serde(default)]
+    pub serv_secret: String,
+    /// How long the links in notifications stay valid, unit: second
+    #[serde(default = "default_link_ttl")]
+    pub link_ttl: u64,
+    /// visor-serv reads this same file
+    #[serde(default)]
+    pub serv: Serv,
     pub lifecycle: Lifecycle,
+    pub reminders: Option<Reminders>,
     pub wechat: Wechat,
     pub whitelist: Whitelist,
+    #[serde(default)]
+    pub schedule: Schedule,
+    #[serde(default)]
+    pub sampling: Sampling,
+    pub pressure: Option<Pressure>,
+    #[serde(default)]
+    pub victim_strategy: VictimStrategyKind,
+    /// Prometheus exporter, only served in daemon mode
+    pub metrics: Option<MetricsExporter>,
+    /// autodeploy records, mapping HTTPS ports to deploy dirs
+    #[serde(default = "default_records_log")]
+    pub records_log: String,
+    /// Where visor keeps what must survive restarts, such as the reminders already sent
+    #[serde(default = "default_state_dir")]
+    pub state_dir: String,
+}
+
+fn default_records_log() -> String {
+    DEFAULT_RECORDS_LOG.to_string()
+}
+
+fn default_state_dir() -> String {
+    DEFAULT_STATE_DIR.to_string()
+}
+
+fn default_link_ttl() -> u64 {
+    7 * 86400
+}
+
+/// Rules deciding when the host is under pressure, replacing `cpu_limit` and `mem_limit` when set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pressure {
+    pub cpu: Option<Threshold>,
+    pub mem: Option<Threshold>,
+    /// Minimum time between two stops, unit: second
+    #[serde(default)]
+    pub cooldown: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Threshold {
+    /// Usage percent at or above which the rule triggers
+    pub trigger: f32,
+    /// Usage percent below which the rule recovers, defaults to `trigger`
+    pub recover: Option<f32>,
+    /// How long usage must stay at or above `trigger`, unit: second
+    #[serde(default)]
+    pub duration: u64,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct Serv {
+    /// Longest lease an owner may hold from now on, unit: day
+    #[serde(default = "default_lease_max_days")]
+    pub lease_max_days: u64,
+    /// How long a portal sign-in lasts, unit: second
+    #[serde(default = "default_session_ttl")]
+    pub session_ttl: u64,
+    /// How long the sign-in link mailed to owners stays valid, unit: second
+    #[serde(default = "default_login_ttl")]
+    pub login_ttl: u64,
+    /// Bearer tokens accepted by the JSON API, which is disabled without any
+    #[serde(default)]
+    pub api_tokens: Vec<String>,
+}
+
+impl Default for Serv {
+    fn default() -> Self {
+        Self {
+            lease_max_days: default_lease_max_days(),
+            session_ttl: default_session_ttl(),
+            login_ttl: default_login_ttl(),
+            api_tokens: Vec::new(),
+        }
+    }
+}
+
+fn default_lease_max_days() -> u64 {
+    30
+}
+
+fn default_session_ttl() -> u64 {
+    8 * 3600
+}
+
+fn default_login_ttl() -> u64 {
+    15 * 60
+}
+
+/// Host metrics sampling, `window` is the number of samples kept for averaging.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Sampling {
+    /// unit: second
+    pub interval: u64,
+    pub window: usize,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            interval: 10,
+            window: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsExporter {
+    /// Address serving `/metrics`, e.g. `127.0.0.1:9101`
+    pub listen: String,
+}
+
+/// Entries are exact values, globs such as `ci-runner-*`, or regexes prefixed with `re:`.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Whitelist {
     pub containers: Option<Vec<String>>,
-    pub containers_map: Option<HashSet<String>>,
+    #[serde(skip)]
+    pub containers_map: Option<PatternSet>,
     pub images: Option<Vec<String>>,
-    pub images_map: Option<HashSet<String>>,
+    #[serde(skip)]
+    pub images_map: Option<PatternSet>,
+    pub volumes: Option<Vec<String>>,
+    #[serde(skip)]
+    pub volumes_map: Option<PatternSet>,
+}
+
+impl Whitelist {
+    /// Builds the `*_map` pattern sets from the entries.
+    pub fn compile(&mut self) -> Result<()> {
+        self.containers_map = Some(PatternSet::new(
+            self.containers.as_deref().unwrap_or_default(),
+        )?);
+        self.images_map = Some(PatternSet::new(self.images.as_deref().unwrap_or_default())?);
+        self.volumes_map = Some(PatternSet::new(self.volumes.as_deref().unwrap_or_default())?);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Slack {
+    /// Incoming webhook URL
+    pub webhook: String,
+    /// Owner email => Slack member ID, used for @-mentions
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+}
+
+/// DingTalk or Feishu custom robot.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Robot {
+    pub webhook: String,
+    /// Signing secret, required when the robot enables signature verification
+    pub secret: Option<String>,
+    /// Owner email => DingTalk user ID or Feishu open ID, used for @-mentions
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+}
+
+/// SMTP settings, notifications are sent to the instance owner.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Email {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// e.g. `visor <visor@example.com>`
+    pub from: String,
+    /// Copied on every notification
+    #[serde(default)]
+    pub cc: Vec<String>,
+    /// Receives the notification when the owner is unknown
+    #[serde(default)]
+    pub fallback: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Plaintext, only for trusted relays
+    None,
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465
+    Tls,
+}
+
+/// Paths of Tera templates overriding the built-in messages.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Templates {
+    pub container_stopped: Option<String>,
+    pub container_expiring: Option<String>,
+    pub container_removed: Option<String>,
+    pub threshold_exceeded: Option<String>,
+    #[serde(skip)]
+    pub compiled: Option<Arc<template::Templates>>,
+}
+
+/// Receives every event as versioned JSON.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Signs `<X-Visor-Timestamp>.<body>` with HMAC-SHA256 into `X-Visor-Signature`
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Event kinds to deliver, all when empty
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Retries on network errors, 429 and 5xx responses
+    #[serde(default = "default_webhook_retries")]
+    pub retries: u32,
+    /// unit: second
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout: u64,
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_timeout() -> u64 {
+    10
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,6 +291,59 @@ pub struct Lifecycle {
     pub pkg: u64,
     pub release: u64,
     pub image_created: u64,
+    #[serde(default = "default_volume_lifecycle")]
+    pub volume: u64,
+}
+
+fn default_volume_lifecycle() -> u64 {
+    7
+}
+
+/// Notifies owners before their exited containers are removed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reminders {
+    /// Days before removal, e.g. `[3, 1]`, each sent once per exit
+    pub days: Vec<u64>,
+}
+
+/// Per-task timers used in daemon mode.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Schedule {
+    pub resources: Option<Trigger>,
+    pub containers: Option<Trigger>,
+    pub dirs: Option<Trigger>,
+    pub images: Option<Trigger>,
+    pub volumes: Option<Trigger>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Trigger {
+    /// unit: second
+    pub interval: Option<u64>,
+    /// e.g. "0 0 3 * * *", seconds field included
+    pub cron: Option<String>,
+    #[serde(skip)]
+    pub cron_schedule: Option<cron::Schedule>,
+}
+
+impl Trigger {
+    fn compile(&mut self, task: &str) -> Result<()> {
+        if let Some(expr) = &self.cron {
+            if self.interval.is_some() {
+                return Err(anyhow!(
+                    "schedule.{}: interval and cron are mutually exclusive",
+                    task
+                ));
+            }
+            let schedule = cron::Schedule::from_str(expr)
+                .map_err(|e| anyhow!("schedule.{}: invalid cron {}: {}", task, expr, e))?;
+            self.cron_schedule = Some(schedule);
+        }
+        if let Some(0) = self.interval {
+            return Err(anyhow!("schedule.{}: interval must be greater than 0", task));
+        }
+        Ok(())
+    }
 }
 
 impl Config {
@@ -43,21 +351,85 @@ impl Config {
         let s = fs::read_to_string(path)?;
         let mut config: Config = serde_yaml::from_str(&s)?;
         
-        let mut containers_map = HashSet::<String>::new();
-        if let Some(containers) = &config.whitelist.containers {
-            for id in containers.iter() {
-                containers_map.insert(id.clone());
+        config.whitelist.compile()?;
+
+        let schedule = &mut config.schedule;
+        for (task, trigger) in [
+            ("resources", &mut schedule.resources),
+            ("containers", &mut schedule.containers),
+            ("dirs", &mut schedule.dirs),
+            ("images", &mut schedule.images),
+            ("volumes", &mut schedule.volumes),
+        ] {
+            if let Some(trigger) = trigger {
+                trigger.compile(task)?;
             }
         }
-        config.whitelist.containers_map = Some(containers_map);
+        if config.sampling.interval == 0 || config.sampling.window == 0 {
+            return Err(anyhow!("sampling.interval and sampling.window must be greater than 0"));
+        }
 
-        let mut images_map = HashSet::<String>::new();
-        if let Some(images) = &config.whitelist.images {
-            for id in images.iter() {
-                images_map.insert(id.clone());
+        if let Some(pressure) = &config.pressure {
+            for (name, threshold) in [("cpu", &pressure.cpu), ("mem", &pressure.mem)] {
+                if let Some(t) = threshold {
+                    if t.recover.unwrap_or(t.trigger) > t.trigger {
+                        return Err(anyhow!(
+                            "pressure.{}: recover must not be greater than trigger",
+                            name
+                        ));
+                    }
+                    // Keep enough samples to cover the sustained duration
+                    let needed = (t.duration / config.sampling.interval) as usize + 1;
+                    if config.sampling.window < needed {
+                        config.sampling.window = needed;
+                    }
+                }
             }
         }
-        config.whitelist.images_map = Some(images_map);
+
+        if !config.serv_url.is_empty() && config.serv_secret.is_empty() {
+            return Err(anyhow!("serv_secret is required to sign the links to serv_url"));
+        }
+
+        config.templates.compiled = Some(Arc::new(template::Templates::load(&config.templates)?));
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(interval: Option<u64>, cron: Option<&str>) -> Trigger {
+        Trigger {
+            interval,
+            cron: cron.map(str::to_string),
+            cron_schedule: None,
+        }
+    }
+
+    #[test]
+    fn test_trigger_compile() {
+        let mut t = trigger(None, Some("0 0 3 * * *"));
+        t.compile("images").unwrap();
+        assert!(t.cron_schedule.is_some());
+        trigger(Some(60), None).compile("images").unwrap();
+
+        for (t, err) in [
+            (
+                trigger(Some(60), Some("0 0 3 * * *")),
+                "schedule.images: interval and cron are mutually exclusive",
+            ),
+            (
+                trigger(Some(0), None),
+                "schedule.images: interval must be greater than 0",
+            ),
+        ] {
+            let mut t = t;
+            assert_eq!(t.compile("images").unwrap_err().to_string(), err);
+        }
+        let mut t = trigger(None, Some("every day"));
+        let err = t.compile("images").unwrap_err().to_string();
+        assert!(err.starts_with("schedule.images: invalid cron every day: "));
+    }
+}
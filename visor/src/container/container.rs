@@ -1,18 +1,31 @@
 use crate::config::Config;
+use crate::container::victim::new_strategy;
+use crate::event::Event;
+use crate::history::{self, Record};
 use crate::instance::{get_instance, Instance};
-use crate::notify::{message_tpl, Notifier};
-use crate::psutil::{get_cpu_usage, get_disk_usage, get_mem_usage};
-use crate::wechat::wechat::Wechat;
+use crate::labels::Policy;
+use crate::lease::Leases;
+use crate::metrics::{observe_action, observe_reclaimed};
+use crate::notify::{container_name, Notifier};
+use crate::plan::{ActionKind, Plan};
+use crate::pressure::PressureState;
+use crate::state;
+use crate::psutil::HostMetrics;
+use crate::whitelist;
 use anyhow::{anyhow, Result};
-use bollard::container::ListContainersOptions;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use bollard::container::{ListContainersOptions, StatsOptions};
 use bollard::errors::Error;
 use bollard::image::ListImagesOptions;
 use bollard::models::ContainerSummary;
+use bollard::volume::ListVolumesOptions;
 use bollard::Docker;
+use futures_util::StreamExt;
 use log::{info, warn};
-use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // https://docs.docker.com/engine/reference/commandline/ps/#filtering
 pub async fn list_containers_by_status(
@@ -39,21 +52,71 @@ pub async fn list_running_containers(docker: &Docker) -> Result<Vec<ContainerSum
     Ok(list_containers_by_status(docker, vec!["running"]).await?)
 }
 
-pub async fn clean_images(docker: &Docker, cfg: &Config) -> Result<()> {
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContainerUsage {
+    /// Percent of one CPU, may exceed 100 on multi-core hosts
+    pub cpu: f64,
+    /// unit: byte
+    pub mem: u64,
+}
+
+pub async fn get_container_usage(docker: &Docker, container_id: &str) -> Result<ContainerUsage> {
+    let opts = StatsOptions {
+        stream: false,
+        one_shot: false,
+    };
+    let stats = docker
+        .stats(container_id, Some(opts))
+        .next()
+        .await
+        .ok_or_else(|| anyhow!("No stats returned for container {}", container_id))??;
+
+    let cpu_delta = stats.cpu_stats.cpu_usage.total_usage as f64
+        - stats.precpu_stats.cpu_usage.total_usage as f64;
+    let system_delta = stats.cpu_stats.system_cpu_usage.unwrap_or_default() as f64
+        - stats.precpu_stats.system_cpu_usage.unwrap_or_default() as f64;
+    let online_cpus = stats.cpu_stats.online_cpus.unwrap_or(1) as f64;
+    let cpu = if cpu_delta > 0.0 && system_delta > 0.0 {
+        cpu_delta / system_delta * online_cpus * 100.0
+    } else {
+        0.0
+    };
+
+    Ok(ContainerUsage {
+        cpu,
+        mem: stats.memory_stats.usage.unwrap_or_default(),
+    })
+}
+
+pub async fn clean_images<T>(
+    docker: &Docker,
+    cfg: &Config,
+    notifier: &T,
+    plan: &mut Plan,
+) -> Result<()>
+where
+    T: Notifier,
+{
+    let whitelist = whitelist::load(cfg);
     let opts = ListImagesOptions::<String> {
         all: true,
         ..Default::default()
     };
     let images = docker.list_images(Some(opts)).await?;
 
-    let t = (SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()
-        - cfg.lifecycle.image_created * 86400) as i64;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
     for image in images.iter() {
+        let policy = Policy::from_labels(&image.labels);
+        if policy.protect {
+            info!("Ignored: image {} is protected by label", image.id);
+            continue;
+        }
+
         // Check image tag
         let mut tag_found = false;
         for tag in image.repo_tags.iter() {
-            if let Some(images_map) = &cfg.whitelist.images_map {
-                if images_map.contains(tag) {
+            if let Some(images_map) = &whitelist.images_map {
+                if images_map.is_match(tag) {
                     info!("Ignored: image {} is in the whitelist", tag);
                     tag_found = true;
                     break;
@@ -65,18 +128,36 @@ pub async fn clean_images(docker: &Docker, cfg: &Config) -> Result<()> {
         }
 
         // Check image id
-        if let Some(images_map) = &cfg.whitelist.images_map {
-            if images_map.contains(&image.id) {
+        if let Some(images_map) = &whitelist.images_map {
+            if images_map.is_match(&image.id) {
                 info!("Ignored: image {} is in the whitelist", image.id);
                 continue;
             }
         }
-        
+
+        let lifecycle = policy.lifecycle_or(cfg.lifecycle.image_created);
+        let t = now.saturating_sub(lifecycle.as_secs()) as i64;
         if image.created.gt(&t) {
             info!("Ignored: image {} was created {}", image.id, image.created);
             continue;
         }
-        if let Err(e) = docker.remove_image(&image.id, None, None).await {
+
+        let reason = format!(
+            "created {} is older than lifecycle {} seconds",
+            image.created,
+            lifecycle.as_secs()
+        );
+        plan.record(ActionKind::RemoveImage, &image.id, reason.clone());
+        if plan.dry_run {
+            continue;
+        }
+        let res = docker.remove_image(&image.id, None, None).await;
+        let record = Record::new(ActionKind::RemoveImage.as_str(), &image.id, &reason)
+            .name(&image.repo_tags.join(","))
+            .result(&res);
+        history::record(&cfg.state_dir, record).await;
+        if let Err(e) = res {
+            observe_action(ActionKind::RemoveImage, false);
             if let Error::DockerResponseServerError {
                 status_code,
                 message,
@@ -88,15 +169,131 @@ pub async fn clean_images(docker: &Docker, cfg: &Config) -> Result<()> {
             }
         } else {
             info!("Deleted image {}", image.id);
+            observe_action(ActionKind::RemoveImage, true);
+            observe_reclaimed(ActionKind::RemoveImage, image.size);
+            let event = Event::ImageRemoved {
+                id: image.id.clone(),
+                tags: image.repo_tags.clone(),
+                reason: format!("created {}", image.created),
+            };
+            if let Err(e) = notifier.notify(&event).await {
+                warn!("Send notification failed: {}", e);
+            }
         }
     }
     Ok(())
 }
 
-pub async fn clean_volumes(docker: &Docker) -> Result<()> {
-    let res = docker.list_volumes::<String>(None).await?;
+pub const DANGLING_VOLUMES_FILE: &str = "dangling_volumes.json";
+
+/// Unix time each volume was first seen dangling, by name, kept in `state_dir`.
+/// A volume can be old yet dangle only briefly, while its container is recreated.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DanglingVolumes {
+    volumes: HashMap<String, i64>,
+}
+
+impl DanglingVolumes {
+    pub fn load(state_dir: &str) -> Result<Self> {
+        state::load(&Path::new(state_dir).join(DANGLING_VOLUMES_FILE))
+    }
+
+    pub fn save(&self, state_dir: &str) -> Result<()> {
+        state::save(&Path::new(state_dir).join(DANGLING_VOLUMES_FILE), self)
+    }
+
+    /// Records the volumes dangling now, forgetting those in use again or removed.
+    pub fn observe(&mut self, dangling: &[&str], now: i64) {
+        self.volumes.retain(|name, _| dangling.contains(&name.as_str()));
+        for name in dangling {
+            self.volumes.entry(name.to_string()).or_insert(now);
+        }
+    }
+
+    pub fn dangling_for(&self, name: &str, now: i64) -> Duration {
+        let since = self.volumes.get(name).copied().unwrap_or(now);
+        Duration::from_secs(now.saturating_sub(since).max(0) as u64)
+    }
+}
+
+pub async fn clean_volumes(docker: &Docker, cfg: &Config, plan: &mut Plan) -> Result<()> {
+    let whitelist = whitelist::load(cfg);
+    let mut filters = HashMap::new();
+    filters.insert("dangling", vec!["true"]);
+    let opts = ListVolumesOptions { filters };
+    let res = docker.list_volumes(Some(opts)).await?;
+
+    let now = Utc::now().timestamp();
+    let mut dangling = DanglingVolumes::load(&cfg.state_dir)?;
+    let names: Vec<&str> = res.volumes.iter().map(|v| v.name.as_str()).collect();
+    // Recording when volumes were first seen dangling is an observation, dry runs too
+    dangling.observe(&names, now);
+    dangling.save(&cfg.state_dir)?;
+    if res.volumes.is_empty() {
+        info!("No dangling volumes found");
+        return Ok(());
+    }
+
+    // Sizes are only reported by the system data usage API
+    let sizes: HashMap<String, i64> = match docker.df().await {
+        Ok(usage) => usage
+            .volumes
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|v| v.usage_data.map(|u| (v.name, u.size)))
+            .collect(),
+        Err(e) => {
+            warn!("Get volumes size failed: {}", e);
+            HashMap::new()
+        }
+    };
+
+    let mut reclaimed: i64 = 0;
     for volume in res.volumes.iter() {
-        if let Err(e) = docker.remove_volume(&volume.name, None).await {
+        if let Some(volumes_map) = &whitelist.volumes_map {
+            if volumes_map.is_match(&volume.name) {
+                info!("Ignored: volume {} is in the whitelist", volume.name);
+                continue;
+            }
+        }
+
+        let policy = Policy::from_labels(&volume.labels);
+        if policy.protect {
+            info!("Ignored: volume {} is protected by label", volume.name);
+            continue;
+        }
+        let lifecycle = policy.lifecycle_or(cfg.lifecycle.volume);
+
+        // Measured from the time the volume lost its last container, not its creation
+        let age = dangling.dangling_for(&volume.name, now);
+        if age.lt(&lifecycle) {
+            info!(
+                "Ignored: volume {} has been dangling for {} seconds",
+                volume.name,
+                age.as_secs()
+            );
+            continue;
+        }
+
+        let size = sizes.get(&volume.name).copied().unwrap_or(-1);
+        let reason = format!(
+            "dangling for {} seconds, lifecycle {} seconds, size {} bytes",
+            age.as_secs(),
+            lifecycle.as_secs(),
+            size
+        );
+        plan.record(ActionKind::RemoveVolume, &volume.name, reason.clone());
+        if plan.dry_run {
+            continue;
+        }
+
+        let res = docker.remove_volume(&volume.name, None).await;
+        let record = Record::new(ActionKind::RemoveVolume.as_str(), &volume.name, &reason)
+            .owner(policy.owner.clone())
+            .result(&res);
+        history::record(&cfg.state_dir, record).await;
+        if let Err(e) = res {
+            observe_action(ActionKind::RemoveVolume, false);
             if let Error::DockerResponseServerError {
                 status_code,
                 message,
@@ -107,87 +304,181 @@ pub async fn clean_volumes(docker: &Docker) -> Result<()> {
                 }
             }
         } else {
-            info!("Deleted volume {}", volume.name);
+            info!("Deleted volume {}, size {} bytes", volume.name, size);
+            observe_action(ActionKind::RemoveVolume, true);
+            observe_reclaimed(ActionKind::RemoveVolume, size);
+            if size > 0 {
+                reclaimed += size;
+            }
         }
     }
+    if !plan.dry_run {
+        info!("Reclaimed {} bytes from volumes", reclaimed);
+    }
     Ok(())
 }
 
-pub fn parse_status_time(s: &str) -> Vec<String> {
-    let re = Regex::new(r"(Exited |Up )(\([0-9]+\) )?(About )?").unwrap();
-    let s = re.replace_all(s, "").to_string();
-    let items = s.split(" ").collect::<Vec<&str>>();
-    vec![items[0].to_string(), items[1].to_string()]
+/// Parses a Docker RFC 3339 timestamp, the zero value `0001-01-01T00:00:00Z` means unset.
+pub fn parse_docker_time(s: &str) -> Option<DateTime<Utc>> {
+    let t = DateTime::parse_from_rfc3339(s).ok()?.with_timezone(&Utc);
+    if t.year() <= 1 {
+        None
+    } else {
+        Some(t)
+    }
 }
 
-pub fn status_into_duration(s: &str) -> Result<Duration> {
-    if s.is_empty() {
-        return Err(anyhow!("Empty status"));
-    }
+fn elapsed_since(t: DateTime<Utc>) -> Duration {
+    Utc::now().signed_duration_since(t).to_std().unwrap_or_default()
+}
 
-    let items = parse_status_time(s);
+fn created_at(container: &ContainerSummary) -> Option<DateTime<Utc>> {
+    container
+        .created
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+}
 
-    let p1 = &items[0];
-    let num;
-    if p1.eq("an") {
-        num = 1;
-    } else {
-        num = p1.parse::<u64>().unwrap_or_default();
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContainerTimes {
+    pub created: Option<DateTime<Utc>>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub async fn inspect_container_times(docker: &Docker, container_id: &str) -> Result<ContainerTimes> {
+    let res = docker.inspect_container(container_id, None).await?;
+    let state = res.state.unwrap_or_default();
+    Ok(ContainerTimes {
+        created: res.created.as_deref().and_then(parse_docker_time),
+        started_at: state.started_at.as_deref().and_then(parse_docker_time),
+        finished_at: state.finished_at.as_deref().and_then(parse_docker_time),
+    })
+}
+
+/// How long a running container has been up, falling back to its creation time.
+pub async fn container_uptime(docker: &Docker, container: &ContainerSummary) -> Duration {
+    let container_id = container.id.clone().unwrap_or_default();
+    let times = inspect_container_times(docker, &container_id)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Inspect container {} failed: {}", container_id, e);
+            ContainerTimes::default()
+        });
+    times
+        .started_at
+        .or(times.created)
+        .or_else(|| created_at(container))
+        .map(elapsed_since)
+        .unwrap_or_default()
+}
+
+/// How long an exited container has been stopped, falling back to its creation time.
+pub async fn container_exited_duration(
+    docker: &Docker,
+    container: &ContainerSummary,
+) -> Result<Duration> {
+    let container_id = container.id.clone().unwrap_or_default();
+    let times = inspect_container_times(docker, &container_id).await?;
+    times
+        .finished_at
+        .or(times.created)
+        .or_else(|| created_at(container))
+        .map(elapsed_since)
+        .ok_or_else(|| anyhow!("No timestamps found for container {}", container_id))
+}
+
+/// A one-shot run starts with an empty window, rules with a duration could never fire
+/// without waiting for it to cover them.
+pub async fn wait_for_window(metrics: &HostMetrics, pressure: &PressureState) -> Result<()> {
+    if let Some(remaining) = pressure.warm_up(&metrics.samples(), Instant::now()) {
+        info!(
+            "Waiting {} seconds for samples covering the sustained thresholds",
+            remaining.as_secs()
+        );
     }
-    
-    let unit = items[1].as_str();
-    match unit {
-        "seconds" => Ok(Duration::from_secs(num)),
-        "minutes" => Ok(Duration::from_secs(num * 60)),
-        "hours" => Ok(Duration::from_secs(num * 60 * 60)),
-        "days" => Ok(Duration::from_secs(num * 60 * 60 * 24)),
-        "weeks" => Ok(Duration::from_secs(num * 60 * 60 * 24 * 7)),
-        "months" => Ok(Duration::from_secs(num * 60 * 60 * 24 * 7 * 30)),
-        "years" => Ok(Duration::from_secs(num * 60 * 60 * 24 * 7 * 30 * 365)),
-        _ => Err(anyhow::anyhow!("Unknown unit: {}", unit)),
+    while pressure
+        .warm_up(&metrics.samples(), Instant::now())
+        .is_some()
+    {
+        metrics.next().await?;
     }
+    Ok(())
 }
 
-pub async fn stop_containers<'a, T>(
+pub async fn stop_containers<T>(
     docker: &Docker,
     cfg: &Config,
     notifier: &T,
-    wechat: &mut Wechat<'a>,
+    metrics: &HostMetrics,
+    pressure: &mut PressureState,
+    plan: &mut Plan,
 ) -> Result<()>
 where
     T: Notifier,
 {
+    let strategy = new_strategy(cfg);
+    let whitelist = whitelist::load(cfg);
+    let mut sample = metrics.current().await?;
+    let mut exceeded = false;
     loop {
-        let cpu_usage = get_cpu_usage()?;
-        let mem_usage = get_mem_usage()?;
-        let disk_usage = get_disk_usage()?;
+        let avg = metrics.average().unwrap_or(sample);
         info!(
-            "CPU: {}%, MEM: {}%, DISK: {}%s",
-            cpu_usage, mem_usage, disk_usage
+            "CPU: {}% (avg {}%), MEM: {}% (avg {}%), DISK: {}%",
+            sample.cpu, avg.cpu, sample.mem, avg.mem, sample.disk
         );
-        if cpu_usage < cfg.cpu_limit && mem_usage < cfg.mem_limit {
+        let reason = match pressure.evaluate(&metrics.samples(), Instant::now()) {
+            Some(reason) => reason,
+            None => break,
+        };
+        if let Some(remaining) = pressure.cooldown_remaining(Instant::now()) {
+            info!(
+                "Under pressure ({}), cooling down for another {} seconds",
+                reason,
+                remaining.as_secs()
+            );
             break;
         }
 
-        let mut containers: Vec<ContainerSummary> = list_running_containers(docker)
+        // 每轮只通知一次超限
+        if !exceeded && !plan.dry_run {
+            exceeded = true;
+            let event = Event::ThresholdExceeded {
+                metrics: sample,
+                reason: reason.clone(),
+            };
+            if let Err(e) = notifier.notify(&event).await {
+                warn!("Send notification failed: {}", e);
+            }
+        }
+
+        let containers: Vec<ContainerSummary> = list_running_containers(docker)
             .await?
             .into_iter()
             .filter(|c| {
                 // Check container id
                 if let Some(id) = &c.id.clone() {
-                    if let Some(containers_map) = &cfg.whitelist.containers_map {
-                        if containers_map.contains(id) {
+                    if let Some(containers_map) = &whitelist.containers_map {
+                        if containers_map.is_match(id) {
                             info!("Ignored: container {} is in the whitelist", id);
                             return false;
                         }
                     }
                 }
 
+                // Check protect label
+                if Policy::from_container(c).protect {
+                    info!(
+                        "Ignored: container {} is protected by label",
+                        c.id.clone().unwrap_or_default()
+                    );
+                    return false;
+                }
+
                 // Check container name
                 if let Some(names) = c.names.clone() {
                     for name in names.iter() {
-                        if let Some(containers_map) = &cfg.whitelist.containers_map {
-                            if containers_map.contains(name) {
+                        if let Some(containers_map) = &whitelist.containers_map {
+                            if containers_map.is_match(name) {
                                 info!("Ignored: container {} is in the whitelist", name);
                                 return false;
                             }
@@ -202,46 +493,86 @@ where
             return Ok(());
         }
 
-        containers.sort_by(|a, b| {
-            let a_time =
-                status_into_duration(&a.status.clone().unwrap_or_default()).unwrap_or_default();
-            let b_time =
-                status_into_duration(&b.status.clone().unwrap_or_default()).unwrap_or_default();
-            b_time.cmp(&a_time)
+        // Leased containers are only stopped when no other container is left
+        let leases = Leases::load(&cfg.state_dir).unwrap_or_else(|e| {
+            warn!("Load leases failed: {}", e);
+            Leases::default()
         });
+        let now = Utc::now().timestamp();
+        let (leased, unleased): (Vec<_>, Vec<_>) = containers.into_iter().partition(|c| {
+            leases
+                .active(c.id.as_deref().unwrap_or_default(), now)
+                .is_some()
+        });
+        let containers = if unleased.is_empty() {
+            info!("All {} running containers are leased", leased.len());
+            leased
+        } else {
+            unleased
+        };
 
-        let container = &containers[0];
+        let container = match strategy.select(docker, containers).await? {
+            Some(container) => container,
+            None => {
+                info!("No container selected by strategy {}", strategy.name());
+                return Ok(());
+            }
+        };
+        let container = &container;
+        let running_time = container_uptime(docker, container).await;
         let container_id = &container.id.clone().unwrap_or_default();
-        let instance = get_instance(&container).unwrap_or_else(|e| {
+        let instance = get_instance(&container, &cfg.records_log).unwrap_or_else(|e| {
             warn!("Get instance owner failed: {}", e);
             Instance::default()
         });
         info!("Owner email: {}", instance.owner);
 
-        if let Err(e) = docker.stop_container(container_id, None).await {
-            if let Error::DockerResponseServerError {
-                status_code,
-                message,
-            } = e
-            {
-                if status_code == 500 {
-                    return Err(anyhow!("{}", message));
-                }
-            }
+        let detail = format!(
+            "selected by {} strategy (up {}s), {}",
+            strategy.name(),
+            running_time.as_secs(),
+            reason
+        );
+        plan.record(ActionKind::StopContainer, container_id, detail.clone());
+        if plan.dry_run {
+            // Usage will not drop without actually stopping, so only the first victim is reported
+            break;
+        }
+
+        let res = match docker.stop_container(container_id, None).await {
+            // 304: the container has already stopped
+            Err(Error::DockerResponseServerError {
+                status_code: 304, ..
+            }) => Ok(()),
+            res => res,
+        };
+        let record = Record::new(ActionKind::StopContainer.as_str(), container_id, &detail)
+            .name(&container_name(container))
+            .owner(Some(instance.owner.clone()))
+            .metrics(&sample)
+            .result(&res);
+        history::record(&cfg.state_dir, record).await;
+        if let Err(e) = res {
+            observe_action(ActionKind::StopContainer, false);
+            return Err(anyhow!("Stop container {} failed: {}", container_id, e));
         }
         info!("Stopped container: {}", container_id);
+        observe_action(ActionKind::StopContainer, true);
+        pressure.stopped(Instant::now());
 
-        let mut user_id: Option<&String>;
-        user_id = wechat.users.get(&instance.owner);
-        if user_id.is_none() {
-            wechat
-                .map_users_by_department(cfg.wechat.department_id)
-                .await?;
-            user_id = wechat.users.get(&instance.owner);
+        let event = Event::ContainerStopped {
+            container: container.clone(),
+            instance,
+            metrics: sample,
+            reason,
+            running_time,
+        };
+        if let Err(e) = notifier.notify(&event).await {
+            warn!("Send notification failed: {}", e);
         }
 
-        let msg = message_tpl(container, &instance, &cfg);
-        notifier.notify(&msg, user_id).await?;
+        // Wait for a fresh sample so the stop takes effect before picking the next one
+        sample = metrics.next().await?;
     }
     Ok(())
 }
@@ -259,22 +590,17 @@ pub async fn map_existed_containers(docker: &Docker) -> Result<Option<HashMap<St
 
     let mut containers_map: HashMap<String, ComplexContainer> = HashMap::new();
     for container in containers {
-        if let Some(container_id) = &container.id {
-            if let Some(status) = &container.status {
-                let exist_duration = status_into_duration(status);
-                match exist_duration {
-                    Ok(exist_time) => {
-                        containers_map.insert(container_id.clone(), ComplexContainer {
-                            container,
-                            exist_duration: exist_time,
-                        });
-                    }
-                    Err(e) => {
-                        warn!("Status into duration failed: {}, err: {}", status, e);
-                    }
+        if let Some(container_id) = container.id.clone() {
+            match container_exited_duration(docker, &container).await {
+                Ok(exist_duration) => {
+                    containers_map.insert(container_id, ComplexContainer {
+                        container,
+                        exist_duration,
+                    });
+                }
+                Err(e) => {
+                    warn!("Get exited duration of container {} failed: {}", container_id, e);
                 }
-            } else {
-                warn!("Container status not found: {:?}", container);
             }
         } else {
             warn!("Container id not found: {:?}", container)
@@ -283,18 +609,64 @@ pub async fn map_existed_containers(docker: &Docker) -> Result<Option<HashMap<St
     return Ok(Some(containers_map));
 }
 
-pub async fn clean_exited_containers(docker: &Docker, lifecycle: u64) -> Result<()> {
+pub async fn clean_exited_containers<T>(
+    docker: &Docker,
+    cfg: &Config,
+    notifier: &T,
+    plan: &mut Plan,
+) -> Result<()>
+where
+    T: Notifier,
+{
+    let leases = Leases::load(&cfg.state_dir)?;
+    let now = Utc::now().timestamp();
     let existed_containers = map_existed_containers(docker).await?;
 
     if let Some(existed_containers_map) = existed_containers {
-        let d = Duration::from_secs(86400 * lifecycle);
         for (container_id, container) in existed_containers_map {
+            let policy = Policy::from_container(&container.container);
+            if policy.protect {
+                info!("Ignored: container {} is protected by label", container_id);
+                continue;
+            }
+
+            if let Some(lease) = leases.active(&container_id, now) {
+                info!(
+                    "Ignored: container {} is leased for another {} seconds",
+                    container_id,
+                    lease.until - now
+                );
+                continue;
+            }
+
+            let d = policy.lifecycle_or(cfg.lifecycle.container);
             if container.exist_duration.lt(&d) {
-                info!("Ignored: container {} exited {} seconds", container_id, d.as_secs());
+                info!(
+                    "Ignored: container {} exited {} seconds",
+                    container_id,
+                    container.exist_duration.as_secs()
+                );
+                continue;
+            }
+
+            let reason = format!(
+                "exited {} seconds ago, lifecycle {} seconds",
+                container.exist_duration.as_secs(),
+                d.as_secs()
+            );
+            plan.record(ActionKind::RemoveContainer, &container_id, reason.clone());
+            if plan.dry_run {
                 continue;
             }
 
-            if let Err(e) = docker.remove_container(&container_id, None).await {
+            let res = docker.remove_container(&container_id, None).await;
+            let record = Record::new(ActionKind::RemoveContainer.as_str(), &container_id, &reason)
+                .name(&container_name(&container.container))
+                .owner(policy.owner.clone())
+                .result(&res);
+            history::record(&cfg.state_dir, record).await;
+            if let Err(e) = res {
+                observe_action(ActionKind::RemoveContainer, false);
                 if let Error::DockerResponseServerError {
                     status_code,
                     message,
@@ -306,6 +678,14 @@ pub async fn clean_exited_containers(docker: &Docker, lifecycle: u64) -> Result<
                 }
             } else {
                 info!("Removed container {}", container_id);
+                observe_action(ActionKind::RemoveContainer, true);
+                let event = Event::ContainerRemoved {
+                    reason: format!("exited {} seconds ago", container.exist_duration.as_secs()),
+                    container: container.container,
+                };
+                if let Err(e) = notifier.notify(&event).await {
+                    warn!("Send notification failed: {}", e);
+                }
             }
         }
     } else {
@@ -318,16 +698,31 @@ pub async fn clean_exited_containers(docker: &Docker, lifecycle: u64) -> Result<
 #[cfg(test)]
 mod tests {
     #[test]
-    fn test_parse_status_time() {
-        let s = "Up 2 weeks";
-        let items = super::parse_status_time(s);
-        assert_eq!(items[0], "2");
-        assert_eq!(items[1], "weeks");
-
-        let s = "Exited (137) 9 hours ago";
-        let items = super::parse_status_time(s);
-        assert_eq!(items[0], "9");
-        assert_eq!(items[1], "hours");
+    fn test_parse_docker_time() {
+        let t = super::parse_docker_time("2022-05-01T08:30:00.123456789Z").unwrap();
+        assert_eq!(t.timestamp(), 1651393800);
+
+        assert!(super::parse_docker_time("0001-01-01T00:00:00Z").is_none());
+        assert!(super::parse_docker_time("").is_none());
+    }
+
+    #[test]
+    fn test_dangling_volumes() {
+        let day = 86400;
+        let lifecycle = std::time::Duration::from_secs(7 * day as u64);
+        let mut dangling = super::DanglingVolumes::default();
+
+        // Created months ago, but only just lost its container
+        dangling.observe(&["old"], 100 * day);
+        assert!(dangling.dangling_for("old", 100 * day) < lifecycle);
+        assert!(dangling.dangling_for("old", 106 * day) < lifecycle);
+        assert!(dangling.dangling_for("old", 107 * day) >= lifecycle);
+
+        // In use again, the dangling time starts over
+        dangling.observe(&[], 105 * day);
+        dangling.observe(&["old"], 106 * day);
+        assert!(dangling.dangling_for("old", 110 * day) < lifecycle);
+        assert_eq!(dangling.dangling_for("other", 110 * day).as_secs(), 0);
     }
 
     use bollard::Docker;
@@ -1 +1,2 @@
 pub mod container;
+pub mod victim;
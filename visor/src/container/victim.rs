@@ -0,0 +1,310 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bollard::models::ContainerSummary;
+use bollard::Docker;
+use futures_util::future::join_all;
+use log::warn;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::container::container::{container_uptime, get_container_usage, ContainerUsage};
+use crate::instance::{get_instance, OWNER_FILE};
+use crate::labels::Policy;
+use crate::lease::Leases;
+
+/// Decides which running container is stopped first when the host is under pressure.
+#[async_trait]
+pub trait VictimStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn select(
+        &self,
+        docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VictimStrategyKind {
+    #[default]
+    LongestUptime,
+    HighestCpu,
+    HighestMemory,
+    OldestLease,
+    LowestPriority,
+}
+
+pub fn new_strategy(cfg: &Config) -> Box<dyn VictimStrategy> {
+    match cfg.victim_strategy {
+        VictimStrategyKind::LongestUptime => Box::new(LongestUptime),
+        VictimStrategyKind::HighestCpu => Box::new(HighestCpu),
+        VictimStrategyKind::HighestMemory => Box::new(HighestMemory),
+        VictimStrategyKind::OldestLease => Box::new(OldestLease {
+            records_log: cfg.records_log.clone(),
+            state_dir: cfg.state_dir.clone(),
+        }),
+        VictimStrategyKind::LowestPriority => Box::new(LowestPriority),
+    }
+}
+
+/// Returns the candidate with the greatest key, the first one wins on ties.
+fn pick_max<K: PartialOrd>(keyed: Vec<(K, ContainerSummary)>) -> Option<ContainerSummary> {
+    let mut best: Option<(K, ContainerSummary)> = None;
+    for (key, container) in keyed {
+        match &best {
+            Some((best_key, _)) if key.le(best_key) => {}
+            _ => best = Some((key, container)),
+        }
+    }
+    best.map(|(_, container)| container)
+}
+
+/// Stats of every candidate, fetched concurrently since each one waits for a CPU sample.
+async fn usages(
+    docker: &Docker,
+    candidates: Vec<ContainerSummary>,
+) -> Vec<(Option<ContainerUsage>, ContainerSummary)> {
+    join_all(candidates.into_iter().map(|c| async move {
+        let id = c.id.clone().unwrap_or_default();
+        let usage = get_container_usage(docker, &id)
+            .await
+            .map_err(|e| warn!("Get stats of container {} failed: {}", id, e))
+            .ok();
+        (usage, c)
+    }))
+    .await
+}
+
+pub struct LongestUptime;
+
+#[async_trait]
+impl VictimStrategy for LongestUptime {
+    fn name(&self) -> &'static str {
+        "longest_uptime"
+    }
+
+    async fn select(
+        &self,
+        docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>> {
+        let mut keyed = Vec::with_capacity(candidates.len());
+        for c in candidates {
+            keyed.push((container_uptime(docker, &c).await, c));
+        }
+        Ok(pick_max(keyed))
+    }
+}
+
+pub struct HighestCpu;
+
+#[async_trait]
+impl VictimStrategy for HighestCpu {
+    fn name(&self) -> &'static str {
+        "highest_cpu"
+    }
+
+    async fn select(
+        &self,
+        docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>> {
+        let keyed = usages(docker, candidates)
+            .await
+            .into_iter()
+            .map(|(usage, c)| (usage.map_or(0.0, |u| u.cpu), c))
+            .collect();
+        Ok(pick_max(keyed))
+    }
+}
+
+pub struct HighestMemory;
+
+#[async_trait]
+impl VictimStrategy for HighestMemory {
+    fn name(&self) -> &'static str {
+        "highest_memory"
+    }
+
+    async fn select(
+        &self,
+        docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>> {
+        let keyed = usages(docker, candidates)
+            .await
+            .into_iter()
+            .map(|(usage, c)| (usage.map_or(0, |u| u.mem), c))
+            .collect();
+        Ok(pick_max(keyed))
+    }
+}
+
+/// The owner lease starts when the owner file is written into the deploy dir,
+/// and again whenever the owner extends it through visor-serv.
+/// Containers without a known owner are treated as the newest lease.
+pub struct OldestLease {
+    records_log: String,
+    state_dir: String,
+}
+
+#[async_trait]
+impl VictimStrategy for OldestLease {
+    fn name(&self) -> &'static str {
+        "oldest_lease"
+    }
+
+    async fn select(
+        &self,
+        _docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>> {
+        let now = SystemTime::now();
+        let leases = Leases::load(&self.state_dir).unwrap_or_else(|e| {
+            warn!("Load leases failed: {}", e);
+            Leases::default()
+        });
+        let keyed = candidates
+            .into_iter()
+            .map(|c| {
+                let owned = get_instance(&c, &self.records_log)
+                    .ok()
+                    .and_then(|inst| {
+                        fs::metadata(format!("{}/{}", inst.deploy_dir, OWNER_FILE)).ok()
+                    })
+                    .and_then(|m| m.modified().ok());
+                let extended = leases
+                    .extended_at(c.id.as_deref().unwrap_or_default())
+                    .map(|secs| UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64));
+                (lease_age(owned, extended, now), c)
+            })
+            .collect();
+        Ok(pick_max(keyed))
+    }
+}
+
+/// Time since the lease last started, zero without one.
+fn lease_age(owned: Option<SystemTime>, extended: Option<SystemTime>, now: SystemTime) -> Duration {
+    owned
+        .max(extended)
+        .and_then(|t| now.duration_since(t).ok())
+        .unwrap_or_default()
+}
+
+/// Lower `visor.priority` is stopped first, ties fall back to the longest uptime.
+pub struct LowestPriority;
+
+#[async_trait]
+impl VictimStrategy for LowestPriority {
+    fn name(&self) -> &'static str {
+        "lowest_priority"
+    }
+
+    async fn select(
+        &self,
+        docker: &Docker,
+        candidates: Vec<ContainerSummary>,
+    ) -> Result<Option<ContainerSummary>> {
+        let mut keyed = Vec::with_capacity(candidates.len());
+        for c in candidates {
+            let uptime = container_uptime(docker, &c).await;
+            keyed.push((priority_key(&c, uptime), c));
+        }
+        Ok(pick_max(keyed))
+    }
+}
+
+fn priority_key(container: &ContainerSummary, uptime: Duration) -> (i64, Duration) {
+    let priority = Policy::from_container(container).priority;
+    (priority.saturating_neg(), uptime)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn container(id: &str, priority: Option<&str>) -> ContainerSummary {
+        ContainerSummary {
+            id: Some(id.to_string()),
+            labels: priority
+                .map(|p| HashMap::from([("visor.priority".to_string(), p.to_string())])),
+            ..Default::default()
+        }
+    }
+
+    fn picked<K: PartialOrd>(keyed: Vec<(K, ContainerSummary)>) -> Option<String> {
+        pick_max(keyed).and_then(|c| c.id)
+    }
+
+    #[test]
+    fn test_pick_max() {
+        assert_eq!(picked::<f64>(vec![]), None);
+        let keyed = vec![
+            (1.0, container("a", None)),
+            (3.0, container("b", None)),
+            (2.0, container("c", None)),
+        ];
+        assert_eq!(picked(keyed).as_deref(), Some("b"));
+        // The first one wins on ties
+        let keyed = vec![
+            (1, container("a", None)),
+            (3, container("b", None)),
+            (3, container("c", None)),
+        ];
+        assert_eq!(picked(keyed).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_lowest_priority() {
+        let hour = Duration::from_secs(3600);
+        let keyed = |containers: Vec<(ContainerSummary, Duration)>| {
+            containers
+                .into_iter()
+                .map(|(c, uptime)| (priority_key(&c, uptime), c))
+                .collect::<Vec<_>>()
+        };
+        let containers = vec![
+            (container("high", Some("high")), 9 * hour),
+            (container("low", Some("low")), hour),
+            (container("normal", None), 5 * hour),
+        ];
+        assert_eq!(picked(keyed(containers)).as_deref(), Some("low"));
+        // Ties fall back to the longest uptime
+        let containers = vec![
+            (container("a", Some("low")), hour),
+            (container("b", Some("-1")), 2 * hour),
+            (container("c", Some("normal")), 9 * hour),
+        ];
+        assert_eq!(picked(keyed(containers)).as_deref(), Some("b"));
+        let containers = vec![(container("min", Some(&i64::MIN.to_string())), hour)];
+        assert_eq!(picked(keyed(containers)).as_deref(), Some("min"));
+    }
+
+    #[test]
+    fn test_oldest_lease() {
+        let now = UNIX_EPOCH + Duration::from_secs(100 * 86400);
+        let days_ago = |d: u64| Some(now - Duration::from_secs(d * 86400));
+        assert_eq!(lease_age(None, None, now), Duration::ZERO);
+        // An extension restarts the lease
+        assert_eq!(
+            lease_age(days_ago(10), days_ago(2), now),
+            Duration::from_secs(2 * 86400)
+        );
+        let keyed = vec![
+            (lease_age(days_ago(3), None, now), container("a", None)),
+            (
+                lease_age(days_ago(10), days_ago(1), now),
+                container("b", None),
+            ),
+            (lease_age(None, None, now), container("c", None)),
+            (lease_age(None, days_ago(5), now), container("d", None)),
+        ];
+        assert_eq!(picked(keyed).as_deref(), Some("d"));
+    }
+}
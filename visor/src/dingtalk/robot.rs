@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+// 自定义机器人说明 https://open.dingtalk.com/document/robots/custom-robot-access
+
+#[derive(Debug, Serialize)]
+pub struct Message<'a> {
+    msgtype: &'static str,
+    markdown: &'a Markdown,
+    at: &'a At,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Markdown {
+    pub title: String,
+    /// Mentioned users must also appear as `@<userId>` in the text
+    pub text: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct At {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub at_user_ids: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub at_mobiles: Vec<String>,
+    pub is_at_all: bool,
+}
+
+impl<'a> Message<'a> {
+    pub fn markdown(m: &'a Markdown, at: &'a At) -> Self {
+        Self {
+            msgtype: "markdown",
+            markdown: m,
+            at,
+        }
+    }
+}
+
+pub struct Robot {
+    webhook: String,
+    secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendMessageResponse {
+    errcode: i32,
+    errmsg: String,
+}
+
+/// Signs `timestamp + "\n" + secret` with the secret, base64 encoded.
+pub fn sign(timestamp: i64, secret: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("{}\n{}", timestamp, secret).as_bytes());
+    Ok(base64::encode(mac.finalize().into_bytes()))
+}
+
+impl<'a> Robot {
+    pub fn new(webhook: String, secret: Option<String>) -> Result<Self> {
+        if webhook.is_empty() {
+            Err(anyhow!("DingTalk webhook is empty"))
+        } else {
+            Ok(Self { webhook, secret })
+        }
+    }
+
+    pub async fn send_message(&self, message: &Message<'a>) -> Result<()> {
+        let mut req = reqwest::Client::new().post(&self.webhook);
+        if let Some(secret) = &self.secret {
+            // unit: millisecond
+            let timestamp = Local::now().timestamp_millis();
+            req = req.query(&[
+                ("timestamp", timestamp.to_string()),
+                ("sign", sign(timestamp, secret)?),
+            ]);
+        }
+        let res = req
+            .json(message)
+            .send()
+            .await?
+            .json::<SendMessageResponse>()
+            .await?;
+        if res.errcode == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to send notification: {}", res.errmsg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use crate::testutil::serve_once;
+
+    #[tokio::test]
+    async fn test_send_signed_markdown() {
+        let (url, handle) = serve_once(r#"{"errcode":0,"errmsg":"ok"}"#).await;
+        let webhook = format!("{}/robot/send?access_token=token", url);
+        let robot = Robot::new(webhook, Some("SECxxx".to_string())).unwrap();
+
+        let m = Markdown {
+            title: "visor".to_string(),
+            text: "hello @user1".to_string(),
+        };
+        let at = At {
+            at_user_ids: vec!["user1".to_string()],
+            ..Default::default()
+        };
+        robot
+            .send_message(&Message::markdown(&m, &at))
+            .await
+            .unwrap();
+
+        let req = handle.await.unwrap();
+        assert!(req
+            .request_line
+            .starts_with("POST /robot/send?access_token=token&timestamp="));
+        let path = req.request_line.split(' ').nth(1).unwrap();
+        let query: HashMap<String, String> = reqwest::Url::parse(&format!("{}{}", url, path))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        let timestamp = query["timestamp"].parse::<i64>().unwrap();
+        assert_eq!(query["sign"], sign(timestamp, "SECxxx").unwrap());
+
+        let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+        assert_eq!(body["msgtype"], "markdown");
+        assert_eq!(body["markdown"]["text"], "hello @user1");
+        assert_eq!(body["at"]["atUserIds"][0], "user1");
+        assert_eq!(body["at"]["isAtAll"], false);
+    }
+
+    #[tokio::test]
+    async fn test_send_error() {
+        let (url, _handle) = serve_once(r#"{"errcode":310000,"errmsg":"sign not match"}"#).await;
+        let robot = Robot::new(url, None).unwrap();
+        let m = Markdown {
+            title: "visor".to_string(),
+            text: "hello".to_string(),
+        };
+        let at = At::default();
+        let err = robot
+            .send_message(&Message::markdown(&m, &at))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("sign not match"));
+    }
+}
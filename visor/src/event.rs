@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use bollard::models::ContainerSummary;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::instance::Instance;
+use crate::labels::Policy;
+use crate::psutil::Sample;
+
+/// Bumped on every incompatible change of the JSON schema.
+pub const EVENT_VERSION: u32 = 1;
+
+/// Something visor did or observed, rendered by each notifier on its own.
+#[derive(Debug, Clone)]
+pub enum Event {
+    ContainerStopped {
+        container: ContainerSummary,
+        instance: Instance,
+        metrics: Sample,
+        reason: String,
+        running_time: Duration,
+    },
+    ContainerRemoved {
+        container: ContainerSummary,
+        reason: String,
+    },
+    /// An exited container will be removed once `remaining` has elapsed.
+    ContainerExpiring {
+        container: ContainerSummary,
+        instance: Instance,
+        remaining: Duration,
+    },
+    ImageRemoved {
+        id: String,
+        tags: Vec<String>,
+        reason: String,
+    },
+    ThresholdExceeded {
+        metrics: Sample,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    ContainerStopped,
+    ContainerRemoved,
+    ContainerExpiring,
+    ImageRemoved,
+    ThresholdExceeded,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::ContainerStopped => "container_stopped",
+            EventKind::ContainerRemoved => "container_removed",
+            EventKind::ContainerExpiring => "container_expiring",
+            EventKind::ImageRemoved => "image_removed",
+            EventKind::ThresholdExceeded => "threshold_exceeded",
+        }
+    }
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::ContainerStopped { .. } => EventKind::ContainerStopped,
+            Event::ContainerRemoved { .. } => EventKind::ContainerRemoved,
+            Event::ContainerExpiring { .. } => EventKind::ContainerExpiring,
+            Event::ImageRemoved { .. } => EventKind::ImageRemoved,
+            Event::ThresholdExceeded { .. } => EventKind::ThresholdExceeded,
+        }
+    }
+
+    /// Owner email, from the instance or else the `visor.owner` label.
+    pub fn owner(&self) -> Option<String> {
+        match self {
+            Event::ContainerStopped {
+                container,
+                instance,
+                ..
+            }
+            | Event::ContainerExpiring {
+                container,
+                instance,
+                ..
+            } if instance.owner.is_empty() => Policy::from_container(container).owner,
+            Event::ContainerStopped { instance, .. }
+            | Event::ContainerExpiring { instance, .. } => Some(instance.owner.clone()),
+            Event::ContainerRemoved { container, .. } => Policy::from_container(container).owner,
+            _ => None,
+        }
+    }
+
+    /// The versioned JSON document delivered to webhooks.
+    pub fn payload(&self) -> Payload {
+        let mut payload = Payload {
+            version: EVENT_VERSION,
+            kind: self.kind(),
+            time: Local::now(),
+            host: hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            reason: None,
+            container: None,
+            image: None,
+            instance: None,
+            owner: self.owner(),
+            metrics: None,
+            expires_at: None,
+        };
+        match self {
+            Event::ContainerStopped {
+                container,
+                instance,
+                metrics,
+                reason,
+                ..
+            } => {
+                payload.container = Some(ContainerInfo::new(container));
+                payload.instance = InstanceInfo::new(instance);
+                payload.metrics = Some(*metrics);
+                payload.reason = Some(reason.clone());
+            }
+            Event::ContainerRemoved { container, reason } => {
+                payload.container = Some(ContainerInfo::new(container));
+                payload.reason = Some(reason.clone());
+            }
+            Event::ContainerExpiring {
+                container,
+                instance,
+                remaining,
+            } => {
+                payload.container = Some(ContainerInfo::new(container));
+                payload.instance = InstanceInfo::new(instance);
+                payload.expires_at = chrono::Duration::from_std(*remaining)
+                    .ok()
+                    .map(|d| payload.time + d);
+            }
+            Event::ImageRemoved { id, tags, reason } => {
+                payload.image = Some(ImageInfo {
+                    id: id.clone(),
+                    tags: tags.clone(),
+                });
+                payload.reason = Some(reason.clone());
+            }
+            Event::ThresholdExceeded { metrics, reason } => {
+                payload.metrics = Some(*metrics);
+                payload.reason = Some(reason.clone());
+            }
+        }
+        payload
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Payload {
+    pub version: u32,
+    pub kind: EventKind,
+    pub time: DateTime<Local>,
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<ContainerInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<InstanceInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Sample>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+}
+
+impl ContainerInfo {
+    fn new(container: &ContainerSummary) -> Self {
+        Self {
+            id: container.id.clone().unwrap_or_default(),
+            name: container
+                .names
+                .as_ref()
+                .and_then(|names| names.first())
+                .map(|name| name.trim_start_matches('/').to_string())
+                .unwrap_or_default(),
+            image: container.image.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    pub id: String,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceInfo {
+    pub deploy_dir: String,
+    pub base_url: String,
+    pub volume: String,
+}
+
+impl InstanceInfo {
+    /// Absent for containers not deployed by autodeploy.
+    fn new(instance: &Instance) -> Option<Self> {
+        if instance.deploy_dir.is_empty() {
+            return None;
+        }
+        Some(Self {
+            deploy_dir: instance.deploy_dir.clone(),
+            base_url: instance.config.base_url.clone(),
+            volume: instance.config.volume.clone(),
+        })
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+// 自定义机器人说明 https://open.feishu.cn/document/client-docs/bot-v3/add-custom-bot
+
+#[derive(Debug, Serialize)]
+pub struct Message<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sign: Option<String>,
+    msg_type: &'static str,
+    card: Card<'a>,
+}
+
+/// An interactive card holding a single markdown element.
+#[derive(Debug, Serialize)]
+pub struct Card<'a> {
+    elements: Vec<Element<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "tag", rename_all = "snake_case")]
+enum Element<'a> {
+    /// Mentions are written inline as `<at id=ou_xxx></at>` or `<at email=a@b.com></at>`
+    Markdown { content: &'a str },
+}
+
+impl<'a> Message<'a> {
+    pub fn markdown(content: &'a str) -> Self {
+        Self {
+            timestamp: None,
+            sign: None,
+            msg_type: "interactive",
+            card: Card {
+                elements: vec![Element::Markdown { content }],
+            },
+        }
+    }
+}
+
+pub struct Robot {
+    webhook: String,
+    secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendMessageResponse {
+    #[serde(default)]
+    code: i32,
+    #[serde(default)]
+    msg: String,
+}
+
+/// Signs an empty message with `timestamp + "\n" + secret` as the key, base64 encoded.
+pub fn sign(timestamp: i64, secret: &str) -> Result<String> {
+    let key = format!("{}\n{}", timestamp, secret);
+    let mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())?;
+    Ok(base64::encode(mac.finalize().into_bytes()))
+}
+
+impl<'a> Robot {
+    pub fn new(webhook: String, secret: Option<String>) -> Result<Self> {
+        if webhook.is_empty() {
+            Err(anyhow!("Feishu webhook is empty"))
+        } else {
+            Ok(Self { webhook, secret })
+        }
+    }
+
+    pub async fn send_message(&self, mut message: Message<'a>) -> Result<()> {
+        if let Some(secret) = &self.secret {
+            // unit: second
+            let timestamp = Local::now().timestamp();
+            message.sign = Some(sign(timestamp, secret)?);
+            message.timestamp = Some(timestamp.to_string());
+        }
+        let res = reqwest::Client::new()
+            .post(&self.webhook)
+            .json(&message)
+            .send()
+            .await?
+            .json::<SendMessageResponse>()
+            .await?;
+        if res.code == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to send notification: {}", res.msg))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::serve_once;
+
+    #[tokio::test]
+    async fn test_send_signed_card() {
+        let (url, handle) = serve_once(r#"{"code":0,"msg":"success","data":{}}"#).await;
+        let webhook = format!("{}/open-apis/bot/v2/hook/xxx", url);
+        let robot = Robot::new(webhook, Some("secret".to_string())).unwrap();
+
+        let content = "hello <at email=a@b.com></at>";
+        robot
+            .send_message(Message::markdown(content))
+            .await
+            .unwrap();
+
+        let req = handle.await.unwrap();
+        assert_eq!(req.request_line, "POST /open-apis/bot/v2/hook/xxx HTTP/1.1");
+        let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+        assert_eq!(body["msg_type"], "interactive");
+        assert_eq!(body["card"]["elements"][0]["tag"], "markdown");
+        assert_eq!(body["card"]["elements"][0]["content"], content);
+        let timestamp = body["timestamp"].as_str().unwrap().parse::<i64>().unwrap();
+        assert_eq!(body["sign"], sign(timestamp, "secret").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_send_error() {
+        let (url, _handle) = serve_once(r#"{"code":19021,"msg":"sign match fail"}"#).await;
+        let robot = Robot::new(url, None).unwrap();
+        let err = robot
+            .send_message(Message::markdown("hello"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("sign match fail"));
+    }
+}
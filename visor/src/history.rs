@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, TimeZone};
+use lazy_static::lazy_static;
+use log::warn;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use tokio::task;
+
+use crate::psutil::{self, Sample};
+
+pub const HISTORY_FILE: &str = "history.db";
+
+/// Actions taken outside of `Plan`, the others are named by `ActionKind`.
+pub const NOTIFY: &str = "notify";
+pub const START_CONTAINER: &str = "start_container";
+pub const EXTEND_LEASE: &str = "extend_lease";
+pub const RELEASE_CONTAINER: &str = "release_container";
+
+const DEFAULT_LIMIT: u32 = 50;
+
+lazy_static! {
+    /// One connection per `state_dir`, opened by the first record.
+    static ref HISTORIES: Mutex<HashMap<String, Arc<Mutex<History>>>> = Default::default();
+}
+
+/// One action taken by visor or visor-serv, with the host usage at that moment.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Record {
+    pub time: i64,
+    pub action: String,
+    /// Container ID, image ID, volume name, path or notifier name
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub reason: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk: Option<f32>,
+}
+
+impl Record {
+    pub fn new(action: &str, target: &str, reason: &str) -> Self {
+        Self {
+            time: Local::now().timestamp(),
+            action: action.to_string(),
+            target: target.to_string(),
+            reason: reason.to_string(),
+            success: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        if !name.is_empty() {
+            self.name = Some(name.to_string());
+        }
+        self
+    }
+
+    pub fn owner(mut self, owner: Option<String>) -> Self {
+        self.owner = owner.filter(|s| !s.is_empty());
+        self
+    }
+
+    pub fn metrics(mut self, sample: &Sample) -> Self {
+        self.cpu = Some(sample.cpu);
+        self.mem = Some(sample.mem);
+        self.disk = Some(sample.disk);
+        self
+    }
+
+    pub fn result<T, E: Display>(mut self, res: &std::result::Result<T, E>) -> Self {
+        if let Err(e) = res {
+            self.success = false;
+            self.error = Some(e.to_string());
+        }
+        self
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            time: row.get(0)?,
+            action: row.get(1)?,
+            target: row.get(2)?,
+            name: row.get(3)?,
+            owner: row.get(4)?,
+            reason: row.get(5)?,
+            success: row.get(6)?,
+            error: row.get(7)?,
+            cpu: row.get(8)?,
+            mem: row.get(9)?,
+            disk: row.get(10)?,
+        })
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(time) = Local.timestamp_opt(self.time, 0).single() {
+            write!(f, "{} ", time.format("%Y-%m-%d %H:%M:%S"))?;
+        }
+        write!(f, "{} {}", self.action, self.target)?;
+        if let Some(name) = &self.name {
+            write!(f, " ({})", name)?;
+        }
+        if let Some(owner) = &self.owner {
+            write!(f, " owner {}", owner)?;
+        }
+        write!(f, ": {}", self.reason)?;
+        let usage: Vec<String> = [("cpu", self.cpu), ("mem", self.mem), ("disk", self.disk)]
+            .iter()
+            .filter_map(|(k, v)| v.map(|v| format!("{} {:.1}%", k, v)))
+            .collect();
+        if !usage.is_empty() {
+            write!(f, " [{}]", usage.join(", "))?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ", failed: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Query {
+    pub action: Option<String>,
+    /// Container ID prefix, full image ID, volume name or container name
+    pub target: Option<String>,
+    pub owner: Option<String>,
+    /// Unix timestamp
+    pub since: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+impl Query {
+    /// Records newer than `since` ago.
+    pub fn since(mut self, since: Duration) -> Self {
+        self.since = Some(Local::now().timestamp() - since.as_secs() as i64);
+        self
+    }
+}
+
+/// Event history kept in SQLite under `state_dir`, shared by visor and visor-serv.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    pub fn open(state_dir: &str) -> Result<Self> {
+        fs::create_dir_all(state_dir)?;
+        let path = Path::new(state_dir).join(HISTORY_FILE);
+        let conn = Connection::open(&path)
+            .map_err(|e| anyhow!("Open history {} failed: {}", path.display(), e))?;
+        // visor and visor-serv may write at the same time
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                target TEXT NOT NULL,
+                name TEXT,
+                owner TEXT,
+                reason TEXT NOT NULL,
+                success INTEGER NOT NULL,
+                error TEXT,
+                cpu REAL,
+                mem REAL,
+                disk REAL
+            );
+            CREATE INDEX IF NOT EXISTS history_time ON history (time);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn insert(&self, r: &Record) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO history (time, action, target, name, owner, reason, success, error, cpu, mem, disk)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                r.time, r.action, r.target, r.name, r.owner, r.reason, r.success, r.error, r.cpu,
+                r.mem, r.disk
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The latest records matching `q`, newest first.
+    pub fn query(&self, q: &Query) -> Result<Vec<Record>> {
+        let mut sql = String::from(
+            "SELECT time, action, target, name, owner, reason, success, error, cpu, mem, disk
+            FROM history WHERE 1 = 1",
+        );
+        let mut args: Vec<&dyn ToSql> = Vec::new();
+        if let Some(action) = &q.action {
+            sql.push_str(" AND action = ?");
+            args.push(action);
+        }
+        if let Some(target) = &q.target {
+            // Short container IDs match by prefix
+            sql.push_str(" AND (substr(target, 1, length(?)) = ? OR name = ?)");
+            args.extend([target as &dyn ToSql, target, target]);
+        }
+        if let Some(owner) = &q.owner {
+            sql.push_str(" AND owner = ?");
+            args.push(owner);
+        }
+        if let Some(since) = &q.since {
+            sql.push_str(" AND time >= ?");
+            args.push(since);
+        }
+        let limit = q.limit.unwrap_or(DEFAULT_LIMIT);
+        sql.push_str(" ORDER BY time DESC, id DESC LIMIT ?");
+        args.push(&limit);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(args.as_slice(), Record::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
+
+fn shared(state_dir: &str) -> Result<Arc<Mutex<History>>> {
+    let mut histories = HISTORIES
+        .lock()
+        .map_err(|_| anyhow!("History lock poisoned"))?;
+    if let Some(history) = histories.get(state_dir) {
+        return Ok(history.clone());
+    }
+    let history = Arc::new(Mutex::new(History::open(state_dir)?));
+    histories.insert(state_dir.to_string(), history.clone());
+    Ok(history)
+}
+
+/// Appends `record` to the history under `state_dir`, off the async workers since
+/// SQLite may wait for the other process. Memory and disk usage are filled in when
+/// the caller has no sample. Failures are only logged, the action itself has
+/// already been taken.
+pub async fn record(state_dir: &str, mut record: Record) {
+    let state_dir = state_dir.to_string();
+    let insert = task::spawn_blocking(move || {
+        if record.mem.is_none() {
+            record.mem = psutil::get_mem_usage().ok();
+            record.disk = psutil::get_disk_usage().ok();
+        }
+        let res = shared(&state_dir).and_then(|history| {
+            history
+                .lock()
+                .map_err(|_| anyhow!("History lock poisoned"))?
+                .insert(&record)
+        });
+        if let Err(e) = res {
+            warn!(
+                "Record history of {} {} failed: {}",
+                record.action, record.target, e
+            );
+        }
+    });
+    if let Err(e) = insert.await {
+        warn!("Record history failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_query() {
+        let dir = std::env::temp_dir().join("visor_history_test");
+        let _ = fs::remove_dir_all(&dir);
+        let history = History::open(dir.to_str().unwrap()).unwrap();
+
+        let mut stopped = Record::new("stop_container", "abcdef123456789", "cpu 95.0% >= 90.0%")
+            .name("project-a")
+            .owner(Some("a@b.com".to_string()))
+            .metrics(&Sample {
+                cpu: 95.0,
+                mem: 60.0,
+                disk: 70.0,
+            });
+        stopped.time = 100;
+        history.insert(&stopped).unwrap();
+        let mut notified = Record::new(NOTIFY, "wechat", "container_stopped")
+            .owner(Some("a@b.com".to_string()))
+            .result(&Err::<(), _>("timeout"));
+        notified.time = 101;
+        history.insert(&notified).unwrap();
+        let mut removed = Record::new("remove_image", "sha256:1234", "created 1");
+        removed.time = 200;
+        history.insert(&removed).unwrap();
+
+        let all = history.query(&Query::default()).unwrap();
+        assert_eq!(
+            all,
+            vec![removed.clone(), notified.clone(), stopped.clone()]
+        );
+        assert!(!all[1].success);
+        assert_eq!(all[1].error.as_deref(), Some("timeout"));
+
+        let owned = Query {
+            owner: Some("a@b.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&owned).unwrap().len(), 2);
+        for target in ["abcdef123456", "project-a"] {
+            let q = Query {
+                target: Some(target.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(history.query(&q).unwrap(), vec![stopped.clone()]);
+        }
+        let q = Query {
+            since: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&q).unwrap(), vec![removed]);
+        let q = Query {
+            action: Some(NOTIFY.to_string()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(history.query(&q).unwrap(), vec![notified]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let dir = std::env::temp_dir().join("visor_history_record");
+        let _ = fs::remove_dir_all(&dir);
+        let state_dir = dir.to_str().unwrap();
+        let sample = Sample {
+            cpu: 95.0,
+            mem: 60.0,
+            disk: 70.0,
+        };
+        for target in ["abc", "def"] {
+            let r = Record::new("stop_container", target, "cpu").metrics(&sample);
+            record(state_dir, r).await;
+        }
+        // Both went through the same connection
+        assert!(HISTORIES.lock().unwrap().contains_key(state_dir));
+        let records = History::open(state_dir)
+            .unwrap()
+            .query(&Query::default())
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].mem, Some(60.0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
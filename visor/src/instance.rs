@@ -1,66 +1,128 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fs;
 use std::ops::Sub;
-use std::process::Command;
-use std::time::{Duration, SystemTime};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Result};
-use bollard::models::ContainerSummary;
+use bollard::models::{ContainerSummary, PortMap};
 use bollard::Docker;
 use log::{info, warn};
 use serde::Deserialize;
 
 use crate::config::Config;
 use crate::container::container::*;
+use crate::history::{self, Record};
+use crate::labels::Policy;
+use crate::metrics::{observe_action, timed};
 use crate::notify::Notifier;
-use crate::wechat::wechat::Wechat;
+use crate::plan::{ActionKind, Plan};
+use crate::pressure::PressureState;
+use crate::psutil::HostMetrics;
+use crate::records::lookup;
+use crate::reminder::remind_expiring_containers;
 
-const OWNER_FILE: &str = ".owner_email";
+pub const OWNER_FILE: &str = ".owner_email";
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Instance {
     pub owner: String,
     pub deploy_dir: String,
     pub config: InstanceConfig,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct InstanceConfig {
     pub base_url: String,
     pub volume: String,
 }
 
-pub fn get_instance_deploy_dir(https_port: i64) -> Result<String> {
-    let cmd = format!(
-        "grep -B1 {} /data/ones/autodeploy/records.log | tail -n 2 | head -n 1",
-        https_port
-    );
-    Ok(exec(&cmd)?)
+pub fn get_instance_deploy_dir(records_log: &str, https_port: i64) -> Result<String> {
+    Ok(lookup(records_log, https_port)?
+        .map(|r| r.deploy_dir)
+        .unwrap_or_default())
 }
 
 // Todo: 获取实例访问地址、容器数据卷
-pub fn get_instance(container: &ContainerSummary) -> Result<Instance> {
-    let mut https_port: i64 = 0;
-    if let Some(ports) = &container.ports {
-        for port in ports.iter() {
-            if port.private_port.eq(&443) {
-                match port.public_port {
-                    Some(p) => https_port = p,
-                    None => return Err(anyhow!("No public port found for 443")),
-                }
-            }
+/// Resolves the instance from the ports the container publishes, which exited
+/// containers no longer list, see `find_instance`.
+pub fn get_instance(container: &ContainerSummary, records_log: &str) -> Result<Instance> {
+    instance_at(container, published_https_port(container), records_log)
+}
+
+/// Like `get_instance`, but falls back to the port bindings of the host config, so the
+/// instance of an exited container is found too.
+pub async fn find_instance(
+    docker: &Docker,
+    container: &ContainerSummary,
+    records_log: &str,
+) -> Result<Instance> {
+    instance_at(container, https_port(docker, container).await, records_log)
+}
+
+/// The host port published for 443, read from the bindings when the container has exited.
+pub async fn https_port(docker: &Docker, container: &ContainerSummary) -> Option<i64> {
+    if let Some(port) = published_https_port(container) {
+        return Some(port);
+    }
+    let container_id = container.id.as_deref()?;
+    match docker.inspect_container(container_id, None).await {
+        Ok(res) => res
+            .host_config
+            .and_then(|c| c.port_bindings)
+            .and_then(|bindings| bound_https_port(&bindings)),
+        Err(e) => {
+            warn!("Inspect container {} failed: {}", container_id, e);
+            None
         }
     }
+}
 
-    let deploy_dir = get_instance_deploy_dir(https_port)?;
-    if deploy_dir.is_empty() {
-        return Err(anyhow!("Deploy dir not found"));
-    }
+fn published_https_port(container: &ContainerSummary) -> Option<i64> {
+    container
+        .ports
+        .iter()
+        .flatten()
+        .find(|port| port.private_port == 443)
+        .and_then(|port| port.public_port)
+}
+
+fn bound_https_port(bindings: &PortMap) -> Option<i64> {
+    bindings
+        .get("443/tcp")?
+        .iter()
+        .flatten()
+        .find_map(|b| b.host_port.as_deref()?.parse().ok())
+}
+
+fn instance_at(
+    container: &ContainerSummary,
+    https_port: Option<i64>,
+    records_log: &str,
+) -> Result<Instance> {
+    // The owner label takes precedence, and identifies containers not deployed by autodeploy
+    let label_owner = Policy::from_container(container).owner;
+    let deploy_dir = match get_container_deploy_dir(https_port, records_log) {
+        Ok(deploy_dir) => deploy_dir,
+        Err(e) => {
+            return match label_owner {
+                Some(owner) => Ok(Instance {
+                    owner,
+                    ..Default::default()
+                }),
+                None => Err(e),
+            }
+        }
+    };
 
     let config = get_instance_config(&deploy_dir).unwrap_or_default();
 
-    let cmd = format!("cat {}/{}", deploy_dir, OWNER_FILE);
-    let owner = exec(&cmd)?;
+    let owner = match label_owner {
+        Some(owner) => owner,
+        None => fs::read_to_string(format!("{}/{}", deploy_dir, OWNER_FILE))?
+            .trim()
+            .to_string(),
+    };
     Ok(Instance {
         owner,
         deploy_dir,
@@ -68,6 +130,15 @@ pub fn get_instance(container: &ContainerSummary) -> Result<Instance> {
     })
 }
 
+fn get_container_deploy_dir(https_port: Option<i64>, records_log: &str) -> Result<String> {
+    let https_port = https_port.ok_or_else(|| anyhow!("No public port found for 443"))?;
+    let deploy_dir = get_instance_deploy_dir(records_log, https_port)?;
+    if deploy_dir.is_empty() {
+        return Err(anyhow!("Deploy dir not found"));
+    }
+    Ok(deploy_dir)
+}
+
 fn get_instance_config(deploy_dir: &str) -> Result<InstanceConfig> {
     let dirs: Vec<String> = fs::read_dir(deploy_dir.clone())?
         .filter(|entry| match entry {
@@ -113,20 +184,6 @@ fn get_instance_config(deploy_dir: &str) -> Result<InstanceConfig> {
     Err(anyhow!("Instance config not found"))
 }
 
-fn exec(cmd: &str) -> Result<String> {
-    let output = Command::new("bash").arg("-c").arg(cmd).output()?;
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-    } else {
-        Err(anyhow!(
-            "Failed to execute cmd [{}]: {} {}",
-            cmd,
-            output.status,
-            String::from_utf8_lossy(&output.stderr)
-        ))
-    }
-}
-
 const PKG_DIR: &str = "/data/ones/pkg";
 const RELEASE_DIR: &str = "/data/release";
 
@@ -143,49 +200,119 @@ pub fn filter_files(dir: &str, lifecycle: u64) -> Result<Vec<String>> {
     Ok(files)
 }
 
-pub fn clean_release(lifecycle: u64) -> Result<()> {
-    let files = filter_files(RELEASE_DIR, lifecycle)?;
+/// The time since `path` was last modified.
+fn modified_ago(path: &str) -> Option<Duration> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+/// Labels of the containers, running or exited, using each deploy dir, see `merge_policies`.
+async fn deploy_dir_policies(
+    docker: &Docker,
+    lifecycle: u64,
+    records_log: &str,
+) -> Result<HashMap<String, Policy>> {
+    let mut containers = list_running_containers(docker).await?;
+    containers.extend(list_exited_containers(docker).await?);
+    let mut owned = Vec::with_capacity(containers.len());
+    for container in containers.iter() {
+        let instance = find_instance(docker, container, records_log)
+            .await
+            .unwrap_or_default();
+        if !instance.deploy_dir.is_empty() {
+            owned.push((instance.deploy_dir, Policy::from_container(container)));
+        }
+    }
+    Ok(merge_policies(owned, lifecycle))
+}
+
+/// A dir shared by several containers is protected by any of them and kept for the
+/// longest lifecycle, those without a label counting the configured `lifecycle` days.
+fn merge_policies(owned: Vec<(String, Policy)>, lifecycle: u64) -> HashMap<String, Policy> {
+    let mut policies = HashMap::<String, Policy>::with_capacity(owned.len());
+    for (deploy_dir, policy) in owned {
+        policies
+            .entry(deploy_dir)
+            .and_modify(|p| {
+                p.protect |= policy.protect;
+                let longest = p
+                    .lifecycle_or(lifecycle)
+                    .max(policy.lifecycle_or(lifecycle));
+                p.lifecycle = Some(longest);
+            })
+            .or_insert(policy);
+    }
+    policies
+}
+
+/// The policy of the deploy dir a release file was unpacked to, matched by name,
+/// e.g. `/data/release/project-a.tar.gz` belongs to `/data/ones/pkg/project-a`.
+fn release_policy<'a>(file: &str, policies: &'a HashMap<String, Policy>) -> Option<&'a Policy> {
+    let name = Path::new(file).file_name()?.to_str()?;
+    policies.iter().find_map(|(dir, policy)| {
+        let base = Path::new(dir).file_name()?.to_str()?;
+        let owned = name == base || name.starts_with(&format!("{}.", base));
+        owned.then_some(policy)
+    })
+}
+
+pub async fn clean_release(
+    docker: &Docker,
+    lifecycle: u64,
+    records_log: &str,
+    state_dir: &str,
+    plan: &mut Plan,
+) -> Result<()> {
+    let policies = deploy_dir_policies(docker, lifecycle, records_log).await?;
+    // Labels may shorten the lifecycle, so every file is checked
+    let files = filter_files(RELEASE_DIR, 0)?;
     if files.is_empty() {
         info!("No release files found");
     } else {
         for f in files.iter() {
-            if let Err(e) = fs::remove_file(f) {
+            let policy = release_policy(f, &policies).cloned().unwrap_or_default();
+            if policy.protect {
+                info!("Ignored: release {} is protected by its container", f);
+                continue;
+            }
+            let d = policy.lifecycle_or(lifecycle);
+            let age = modified_ago(f).unwrap_or_default();
+            if age < d {
+                continue;
+            }
+            let reason = format!(
+                "modified {} seconds ago, lifecycle {} seconds",
+                age.as_secs(),
+                d.as_secs()
+            );
+            plan.record(ActionKind::RemoveRelease, f, reason.clone());
+            if plan.dry_run {
+                continue;
+            }
+
+            let res = fs::remove_file(f);
+            let record = Record::new(ActionKind::RemoveRelease.as_str(), f, &reason).result(&res);
+            history::record(state_dir, record).await;
+            if let Err(e) = res {
                 warn!("Remove release {} failed: {}", f, e);
+                observe_action(ActionKind::RemoveRelease, false);
             } else {
                 info!("Removed release: {}", f);
+                observe_action(ActionKind::RemoveRelease, true);
             }
         }
     }
     Ok(())
 }
 
-pub async fn clean_pkg(docker: &Docker, lifecycle: u64) -> Result<()> {
-    let containers = list_running_containers(docker).await?;
-    let mut m = HashSet::<String>::with_capacity(containers.len());
-    containers.iter().for_each(|container| {
-        let instance = get_instance(container).unwrap_or_default();
-        m.insert(instance.deploy_dir);
-    });
-    drop(containers);
-
-    let existed_containers = list_exited_containers(docker).await?;
-    existed_containers.iter().for_each(|container| {
-        if let Some(ports) = &container.ports {
-            for port in ports.iter() {
-                if port.private_port.eq(&443) {
-                    let https_port = port.public_port;
-                    if let Some(https_port) = https_port {
-                        let deploy_dir = get_instance_deploy_dir(https_port).unwrap_or_default();
-                        if !deploy_dir.is_empty() {
-                            m.insert(deploy_dir);
-                        }
-                    }
-                    break;
-                }
-            }
-        }
-    });
-    drop(existed_containers);
+pub async fn clean_pkg(
+    docker: &Docker,
+    lifecycle: u64,
+    records_log: &str,
+    state_dir: &str,
+    plan: &mut Plan,
+) -> Result<()> {
+    let m = deploy_dir_policies(docker, lifecycle, records_log).await?;
 
     let files = filter_files(PKG_DIR, lifecycle)?;
     if files.is_empty() {
@@ -196,58 +323,200 @@ pub async fn clean_pkg(docker: &Docker, lifecycle: u64) -> Result<()> {
                 info!("Invalid pkg: {}", f);
                 continue;
             }
-            if m.contains(f) {
-                info!("Ignore pkg: {}", f);
+            match m.get(f) {
+                Some(policy) if policy.protect => {
+                    info!("Ignored: pkg {} is protected by its container", f);
+                    continue;
+                }
+                Some(_) => {
+                    info!("Ignore pkg: {}", f);
+                    continue;
+                }
+                None => {}
+            }
+
+            let reason = format!(
+                "modified more than {} days ago and not used by any container",
+                lifecycle
+            );
+            plan.record(ActionKind::RemovePkg, f, reason.clone());
+            if plan.dry_run {
                 continue;
             }
 
-            if let Err(e) = fs::remove_dir_all(f) {
+            // The owner file goes away with the directory
+            let owner = fs::read_to_string(format!("{}/{}", f, OWNER_FILE))
+                .ok()
+                .map(|s| s.trim().to_string());
+            let res = fs::remove_dir_all(f);
+            let record = Record::new(ActionKind::RemovePkg.as_str(), f, &reason)
+                .owner(owner)
+                .result(&res);
+            history::record(state_dir, record).await;
+            if let Err(e) = res {
                 warn!("Remove pkg {} failed: {}", f, e);
+                observe_action(ActionKind::RemovePkg, false);
             } else {
                 info!("Removed pkg: {}", f);
+                observe_action(ActionKind::RemovePkg, true);
             }
         }
     }
     Ok(())
 }
 
-pub async fn monitor<'a, T>(
+pub async fn monitor<T>(
     cfg: &Config,
     docker: &Docker,
     notifier: &T,
-    wechat: &mut Wechat<'a>,
+    metrics: &HostMetrics,
+    plan: &mut Plan,
 ) -> Result<()>
 where
     T: Notifier,
 {
+    // 提醒即将被删除的容器
+    let remind = remind_expiring_containers(docker, cfg, notifier, plan);
+    if let Err(e) = timed("remind_containers", remind).await {
+        warn!("Remind containers failed: {}", e);
+    }
+
     // 清理停止的容器
-    if let Err(e) = clean_exited_containers(docker, cfg.lifecycle.container).await {
+    let clean = clean_exited_containers(docker, cfg, notifier, plan);
+    if let Err(e) = timed("clean_containers", clean).await {
         warn!("Clean containers failed: {}", e);
     };
 
     // 清理部署目录
-    if let Err(e) = clean_pkg(docker, cfg.lifecycle.pkg).await {
+    let clean = clean_pkg(
+        docker,
+        cfg.lifecycle.pkg,
+        &cfg.records_log,
+        &cfg.state_dir,
+        plan,
+    );
+    if let Err(e) = timed("clean_pkg", clean).await {
         warn!("Clean pkg failed: {}", e);
     };
 
-    // 限制 CPU 和内存使用率，并停止过载的容器
-    if let Err(e) = stop_containers(docker, cfg, notifier, wechat).await {
-        warn!("Stop containers failed: {}", e);
-    }
-
     // 清理部署包
-    if let Err(e) = clean_release(cfg.lifecycle.release) {
+    let clean = clean_release(
+        docker,
+        cfg.lifecycle.release,
+        &cfg.records_log,
+        &cfg.state_dir,
+        plan,
+    );
+    if let Err(e) = timed("clean_release", clean).await {
         warn!("Clean release failed: {}", e);
     };
 
     // 清理镜像
-    if let Err(e) = clean_images(docker, cfg).await {
+    if let Err(e) = timed("clean_images", clean_images(docker, cfg, notifier, plan)).await {
         warn!("Clean images failed: {}", e);
     }
 
     // 清理数据卷
-    if let Err(e) = clean_volumes(docker).await {
+    if let Err(e) = timed("clean_volumes", clean_volumes(docker, cfg, plan)).await {
         warn!("Clean volumes failed: {}", e);
     }
+
+    // 限制 CPU 和内存使用率，并停止过载的容器
+    // Last, so the sampler has had the other steps to fill its window. A one-shot run
+    // does not wait for the window to cover the sustained thresholds.
+    let mut pressure = PressureState::new(cfg);
+    if pressure
+        .warm_up(&metrics.samples(), Instant::now())
+        .is_some()
+    {
+        let note = "sustained thresholds are not evaluated, see visor stop --simulate";
+        info!("Ignored: {}", note);
+        plan.not_evaluated(note);
+    }
+    let stop = stop_containers(docker, cfg, notifier, metrics, &mut pressure, plan);
+    if let Err(e) = timed("stop_containers", stop).await {
+        warn!("Stop containers failed: {}", e);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use bollard::models::PortBinding;
+
+    use super::*;
+
+    #[test]
+    fn test_bound_https_port() {
+        let binding = |port: &str| PortBinding {
+            host_ip: Some("0.0.0.0".to_string()),
+            host_port: Some(port.to_string()),
+        };
+        let mut bindings: PortMap = HashMap::new();
+        bindings.insert("80/tcp".to_string(), Some(vec![binding("8080")]));
+        assert_eq!(bound_https_port(&bindings), None);
+
+        bindings.insert("443/tcp".to_string(), Some(vec![binding("18443")]));
+        assert_eq!(bound_https_port(&bindings), Some(18443));
+
+        bindings.insert("443/tcp".to_string(), None);
+        assert_eq!(bound_https_port(&bindings), None);
+    }
+
+    #[test]
+    fn test_release_policy() {
+        let mut policies = HashMap::new();
+        let protected = Policy {
+            protect: true,
+            ..Default::default()
+        };
+        policies.insert("/data/ones/pkg/project-a".to_string(), protected.clone());
+        policies.insert("/data/ones/pkg/project".to_string(), Policy::default());
+
+        let policy = release_policy("/data/release/project-a.tar.gz", &policies);
+        assert_eq!(policy, Some(&protected));
+        assert_eq!(
+            release_policy("/data/release/project-a", &policies),
+            Some(&protected)
+        );
+        assert_eq!(
+            release_policy("/data/release/project.zip", &policies),
+            Some(&Policy::default())
+        );
+        assert_eq!(
+            release_policy("/data/release/project-b.tar.gz", &policies),
+            None
+        );
+    }
+
+    #[test]
+    fn test_shared_release_policy() {
+        let hour = Duration::from_secs(3600);
+        let dir = "/data/ones/pkg/project-a".to_string();
+        let labelled = Policy {
+            lifecycle: Some(hour),
+            ..Default::default()
+        };
+        for owned in [
+            vec![
+                (dir.clone(), labelled.clone()),
+                (dir.clone(), Policy::default()),
+            ],
+            vec![
+                (dir.clone(), Policy::default()),
+                (dir.clone(), labelled.clone()),
+            ],
+        ] {
+            let policies = merge_policies(owned, 30);
+            let policy = release_policy("/data/release/project-a.tar.gz", &policies).unwrap();
+            // The unlabelled container keeps the configured 30 days
+            assert_eq!(policy.lifecycle_or(1), Duration::from_secs(30 * 86400));
+        }
+
+        let policies = merge_policies(vec![(dir, labelled)], 30);
+        let policy = release_policy("/data/release/project-a.tar.gz", &policies).unwrap();
+        assert_eq!(policy.lifecycle_or(30), hour);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bollard::models::ContainerSummary;
+use log::warn;
+
+/// `visor.protect=true` excludes the object from every cleanup and stop.
+pub const PROTECT_LABEL: &str = "visor.protect";
+/// Overrides the configured lifecycle, e.g. `14d`, `12h`, `30m` or `2w`.
+pub const LIFECYCLE_LABEL: &str = "visor.lifecycle";
+/// Owner email, takes precedence over the `.owner_email` file of the instance.
+pub const OWNER_LABEL: &str = "visor.owner";
+/// `low`, `normal`, `high` or an integer, lower priority containers are stopped first.
+pub const PRIORITY_LABEL: &str = "visor.priority";
+
+/// Policy declared through Docker labels on a container, image or volume.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Policy {
+    pub protect: bool,
+    pub lifecycle: Option<Duration>,
+    pub owner: Option<String>,
+    pub priority: i64,
+}
+
+impl Policy {
+    pub fn from_labels(labels: &HashMap<String, String>) -> Self {
+        let lifecycle = labels.get(LIFECYCLE_LABEL).and_then(|s| {
+            let d = parse_lifecycle(s);
+            if d.is_none() {
+                warn!("Invalid {} label: {}", LIFECYCLE_LABEL, s);
+            }
+            d
+        });
+        Self {
+            protect: labels
+                .get(PROTECT_LABEL)
+                .map(|s| parse_bool(s))
+                .unwrap_or_default(),
+            lifecycle,
+            owner: labels
+                .get(OWNER_LABEL)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty()),
+            priority: labels
+                .get(PRIORITY_LABEL)
+                .map(|s| parse_priority(s))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn from_container(container: &ContainerSummary) -> Self {
+        match &container.labels {
+            Some(labels) => Self::from_labels(labels),
+            None => Self::default(),
+        }
+    }
+
+    /// The label lifecycle if set, otherwise the configured one in days.
+    pub fn lifecycle_or(&self, days: u64) -> Duration {
+        self.lifecycle
+            .unwrap_or_else(|| Duration::from_secs(days * 86400))
+    }
+}
+
+fn parse_bool(s: &str) -> bool {
+    matches!(s.trim().to_lowercase().as_str(), "true" | "1" | "yes")
+}
+
+/// Parses `<n><unit>` with unit `m`, `h`, `d` or `w`, a bare number means days.
+pub fn parse_lifecycle(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_lowercase()),
+        _ => (s, 'd'),
+    };
+    let num = num.trim().parse::<u64>().ok()?;
+    let secs = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    num.checked_mul(secs).map(Duration::from_secs)
+}
+
+pub fn parse_priority(s: &str) -> i64 {
+    match s.trim().to_lowercase().as_str() {
+        "low" => -1,
+        "" | "normal" => 0,
+        "high" => 1,
+        other => other.parse::<i64>().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lifecycle() {
+        assert_eq!(
+            parse_lifecycle("14d"),
+            Some(Duration::from_secs(14 * 86400))
+        );
+        assert_eq!(parse_lifecycle("12h"), Some(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_lifecycle("2W"), Some(Duration::from_secs(14 * 86400)));
+        assert_eq!(parse_lifecycle("3"), Some(Duration::from_secs(3 * 86400)));
+        assert_eq!(parse_lifecycle("3y"), None);
+        assert_eq!(parse_lifecycle("d"), None);
+        assert_eq!(parse_lifecycle(""), None);
+        assert_eq!(parse_lifecycle("9999999999999999999w"), None);
+    }
+
+    #[test]
+    fn test_policy_from_labels() {
+        let mut labels = HashMap::new();
+        labels.insert(PROTECT_LABEL.to_string(), "true".to_string());
+        labels.insert(LIFECYCLE_LABEL.to_string(), "14d".to_string());
+        labels.insert(OWNER_LABEL.to_string(), "a@b.com".to_string());
+        labels.insert(PRIORITY_LABEL.to_string(), "high".to_string());
+        let policy = Policy::from_labels(&labels);
+        assert_eq!(
+            policy,
+            Policy {
+                protect: true,
+                lifecycle: Some(Duration::from_secs(14 * 86400)),
+                owner: Some("a@b.com".to_string()),
+                priority: 1,
+            }
+        );
+
+        let policy = Policy::from_labels(&HashMap::new());
+        assert_eq!(policy, Policy::default());
+        assert_eq!(policy.lifecycle_or(7), Duration::from_secs(7 * 86400));
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::state;
+
+/// Written by visor-serv when an owner extends a container, read only by visor.
+pub const LEASES_FILE: &str = "leases.json";
+pub const DEFAULT_EXTEND_DAYS: u64 = 7;
+
+/// Leases by full container ID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Leases {
+    containers: HashMap<String, Lease>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Lease {
+    /// Unix time the lease ends
+    pub until: i64,
+    /// Unix time of the last extension
+    pub extended_at: i64,
+}
+
+impl Leases {
+    pub fn load(state_dir: &str) -> Result<Self> {
+        state::load(&Path::new(state_dir).join(LEASES_FILE))
+    }
+
+    pub fn save(&self, state_dir: &str) -> Result<()> {
+        state::save(&Path::new(state_dir).join(LEASES_FILE), self)
+    }
+
+    /// Extends the lease by `days` from its current end, or from now without one,
+    /// capped at `max_days` from now. Ended leases are dropped. Returns the new end.
+    pub fn extend(&mut self, container_id: &str, days: u64, max_days: u64, now: i64) -> i64 {
+        self.containers.retain(|_, lease| lease.until > now);
+        let start = self
+            .containers
+            .get(container_id)
+            .map_or(now, |lease| lease.until);
+        let until = start
+            .saturating_add(days_secs(days.min(max_days)))
+            .min(now.saturating_add(days_secs(max_days)));
+        self.containers.insert(
+            container_id.to_string(),
+            Lease {
+                until,
+                extended_at: now,
+            },
+        );
+        until
+    }
+
+    pub fn release(&mut self, container_id: &str) {
+        self.containers.remove(container_id);
+    }
+
+    /// The lease of the container if it has not ended yet.
+    pub fn active(&self, container_id: &str, now: i64) -> Option<Lease> {
+        self.containers
+            .get(container_id)
+            .filter(|lease| lease.until > now)
+            .copied()
+    }
+
+    /// Unix time an exited container is removed, once both its lifecycle and its lease have run out.
+    pub fn removal_time(
+        &self,
+        container_id: &str,
+        exited_at: i64,
+        lifecycle: Duration,
+        now: i64,
+    ) -> i64 {
+        let end = exited_at + lifecycle.as_secs() as i64;
+        match self.active(container_id, now) {
+            Some(lease) => lease.until.max(end),
+            None => end,
+        }
+    }
+
+    /// Unix time of the last extension, visor-serv drops the leases that have ended.
+    pub fn extended_at(&self, container_id: &str) -> Option<i64> {
+        self.containers
+            .get(container_id)
+            .map(|lease| lease.extended_at)
+    }
+}
+
+/// Saturates instead of overflowing on days taken from a request.
+fn days_secs(days: u64) -> i64 {
+    i64::try_from(days)
+        .unwrap_or(i64::MAX)
+        .saturating_mul(86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active() {
+        let leases: Leases =
+            serde_json::from_str(r#"{"containers":{"abc":{"until":2000,"extended_at":1000}}}"#)
+                .unwrap();
+        assert_eq!(leases.active("abc", 1500).unwrap().until, 2000);
+        assert!(leases.active("abc", 2000).is_none());
+        assert!(leases.active("def", 1500).is_none());
+        assert_eq!(leases.extended_at("abc"), Some(1000));
+
+        let lifecycle = Duration::from_secs(100);
+        assert_eq!(leases.removal_time("abc", 1000, lifecycle, 1500), 2000);
+        assert_eq!(leases.removal_time("abc", 1950, lifecycle, 1500), 2050);
+        assert_eq!(leases.removal_time("def", 1000, lifecycle, 1500), 1100);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut leases = Leases::default();
+        let now = 1000;
+        assert_eq!(leases.extend("abc", 7, 10, now), now + 7 * 86400);
+        // Extended from the current end, then capped
+        assert_eq!(leases.extend("abc", 7, 10, now), now + 10 * 86400);
+        assert_eq!(leases.extended_at("abc"), Some(now));
+
+        leases.extend("def", 1, 10, now);
+        leases.extend("ghi", 1, 10, now + 2 * 86400);
+        assert!(leases.active("def", now).is_none());
+        leases.release("ghi");
+        assert!(leases.active("ghi", now).is_none());
+
+        assert_eq!(leases.extend("jkl", u64::MAX, 10, now), now + 10 * 86400);
+        assert_eq!(leases.extend("mno", u64::MAX, u64::MAX, now), i64::MAX);
+    }
+}
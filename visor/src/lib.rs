@@ -0,0 +1,27 @@
+pub mod config;
+pub mod container;
+pub mod dingtalk;
+pub mod event;
+pub mod feishu;
+pub mod history;
+pub mod instance;
+pub mod labels;
+pub mod lease;
+pub mod link;
+pub mod locale;
+pub mod metrics;
+pub mod notify;
+pub mod pattern;
+pub mod plan;
+pub mod pressure;
+pub mod psutil;
+pub mod records;
+pub mod reminder;
+pub mod schedule;
+pub mod slack;
+pub mod state;
+pub mod template;
+#[cfg(test)]
+mod testutil;
+pub mod wechat;
+pub mod whitelist;
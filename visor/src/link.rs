@@ -0,0 +1,185 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::locale::Locale;
+
+pub const START_CONTAINER: &str = "start_container";
+pub const EXTEND: &str = "extend";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkError {
+    Unsigned,
+    Expired,
+    Invalid,
+}
+
+/// Signs `<action>:<container_id>:<expires>` with HMAC-SHA256, hex encoded.
+/// visor-serv checks the same signature before acting on the container.
+pub fn sign(secret: &str, action: &str, container_id: &str, expires: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}:{}", action, container_id, expires).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Compares secrets in constant time, through their HMACs since those have the
+/// same length whatever the input.
+pub fn secret_eq(expected: &str, given: &str) -> bool {
+    let mac = |key: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"visor");
+        mac
+    };
+    mac(given)
+        .verify_slice(&mac(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+/// Checks a signature made by `sign`, then that `expires` has not passed,
+/// so a forged expiry is reported as invalid.
+pub fn verify(
+    secret: &str,
+    action: &str,
+    container_id: &str,
+    expires: i64,
+    sig: &str,
+    now: i64,
+) -> Result<(), LinkError> {
+    let sig = decode_hex(sig).ok_or(LinkError::Invalid)?;
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}:{}", action, container_id, expires).as_bytes());
+    mac.verify_slice(&sig).map_err(|_| LinkError::Invalid)?;
+    if expires < now {
+        return Err(LinkError::Expired);
+    }
+    Ok(())
+}
+
+/// None for odd lengths too, the last pair is then out of range.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Link to a visor-serv page acting on the container, rejected after `expires` (Unix time).
+pub fn signed_url(
+    serv_url: &str,
+    secret: &str,
+    action: &str,
+    container_id: &str,
+    expires: i64,
+    locale: Locale,
+) -> String {
+    format!(
+        "{}/{}/{}?lang={}&exp={}&sig={}",
+        serv_url,
+        action,
+        container_id,
+        locale.as_str(),
+        expires,
+        sign(secret, action, container_id, expires)
+    )
+}
+
+/// The target signed by extend links, so the days cannot be raised without the secret.
+pub fn extend_target(container_id: &str, days: u64) -> String {
+    format!("{}:{}", container_id, days)
+}
+
+/// Link to the visor-serv page extending the lease of the container by `days`.
+pub fn extend_url(
+    serv_url: &str,
+    secret: &str,
+    container_id: &str,
+    days: u64,
+    expires: i64,
+    locale: Locale,
+) -> String {
+    format!(
+        "{}/{}/{}?lang={}&days={}&exp={}&sig={}",
+        serv_url,
+        EXTEND,
+        container_id,
+        locale.as_str(),
+        days,
+        expires,
+        sign(secret, EXTEND, &extend_target(container_id, days), expires)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("secret", START_CONTAINER, "abc123abc123", 1651400000),
+            "f7911d2fe25da06c568d27a22625cffec9c935b7b59178469e9afc68212d0e6d"
+        );
+        assert_eq!(
+            signed_url(
+                "https://visor",
+                "secret",
+                START_CONTAINER,
+                "abc123abc123",
+                1651400000,
+                Locale::En
+            ),
+            format!(
+                "https://visor/start_container/abc123abc123?lang=en&exp=1651400000&sig={}",
+                sign("secret", START_CONTAINER, "abc123abc123", 1651400000)
+            )
+        );
+        assert_eq!(
+            extend_url(
+                "https://visor",
+                "secret",
+                "abc123abc123",
+                7,
+                1651400000,
+                Locale::En
+            ),
+            format!(
+                "https://visor/extend/abc123abc123?lang=en&days=7&exp=1651400000&sig={}",
+                sign("secret", EXTEND, "abc123abc123:7", 1651400000)
+            )
+        );
+    }
+
+    #[test]
+    fn test_secret_eq() {
+        assert!(secret_eq("token", "token"));
+        assert!(!secret_eq("token", "token2"));
+        assert!(!secret_eq("token", ""));
+    }
+
+    #[test]
+    fn test_verify() {
+        let sig = sign("secret", START_CONTAINER, "abc123abc123", 1651400000);
+        let verify = |expires, sig: &str, now| {
+            super::verify("secret", START_CONTAINER, "abc123abc123", expires, sig, now)
+        };
+        assert_eq!(verify(1651400000, &sig, 1651300000), Ok(()));
+        assert_eq!(
+            verify(1651400000, &sig, 1651400001),
+            Err(LinkError::Expired)
+        );
+        assert_eq!(
+            verify(1651500000, &sig, 1651300000),
+            Err(LinkError::Invalid)
+        );
+        assert_eq!(
+            verify(1651400000, "abc", 1651300000),
+            Err(LinkError::Invalid)
+        );
+    }
+}
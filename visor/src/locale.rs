@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Language of the messages sent to owners and of the visor-serv pages they link to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    Zh,
+    En,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::Zh, Locale::En];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::Zh => "zh",
+            Locale::En => "en",
+        }
+    }
+
+    /// e.g. `3 天 2 小时` or `3 days 2 hours`, the two most significant units only.
+    pub fn format_duration(&self, d: Duration) -> String {
+        let secs = d.as_secs();
+        let (days, hours, minutes) = (secs / 86400, secs % 86400 / 3600, secs % 3600 / 60);
+        match self {
+            Locale::Zh => {
+                if days > 0 {
+                    format!("{} 天 {} 小时", days, hours)
+                } else if hours > 0 {
+                    format!("{} 小时 {} 分钟", hours, minutes)
+                } else {
+                    format!("{} 分钟", minutes)
+                }
+            }
+            Locale::En => {
+                if days > 0 {
+                    format!(
+                        "{} {} {} {}",
+                        days,
+                        plural(days, "day"),
+                        hours,
+                        plural(hours, "hour")
+                    )
+                } else if hours > 0 {
+                    format!(
+                        "{} {} {} {}",
+                        hours,
+                        plural(hours, "hour"),
+                        minutes,
+                        plural(minutes, "minute")
+                    )
+                } else {
+                    format!("{} {}", minutes, plural(minutes, "minute"))
+                }
+            }
+        }
+    }
+}
+
+fn plural(n: u64, unit: &str) -> String {
+    if n == 1 {
+        unit.to_string()
+    } else {
+        format!("{}s", unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        let zh = Locale::Zh;
+        assert_eq!(zh.format_duration(Duration::from_secs(59)), "0 分钟");
+        assert_eq!(
+            zh.format_duration(Duration::from_secs(3 * 3600 + 120)),
+            "3 小时 2 分钟"
+        );
+        assert_eq!(
+            zh.format_duration(Duration::from_secs(9 * 86400 + 7200)),
+            "9 天 2 小时"
+        );
+
+        let en = Locale::En;
+        assert_eq!(en.format_duration(Duration::from_secs(60)), "1 minute");
+        assert_eq!(
+            en.format_duration(Duration::from_secs(3 * 3600 + 120)),
+            "3 hours 2 minutes"
+        );
+        assert_eq!(
+            en.format_duration(Duration::from_secs(86400 + 3600)),
+            "1 day 1 hour"
+        );
+    }
+}
@@ -1,64 +1,482 @@
-mod config;
-mod container;
-mod instance;
-mod notify;
-mod psutil;
-mod wechat;
-
+use anyhow::{anyhow, Result};
+use bollard::container::ListContainersOptions;
 use bollard::Docker;
-use clap::Parser;
-use log::info;
-use wechat::wechat::Wechat;
+use chrono::{Local, TimeZone, Utc};
+use clap::{ArgEnum, Parser, Subcommand};
+use log::warn;
 
-use crate::config::Config;
-use crate::container::container::*;
-use crate::instance::*;
-use crate::notify::WechatNotifier;
-use crate::psutil::*;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use visor::config::Config;
+use visor::container::container::*;
+use visor::history::{History, Query};
+use visor::instance::*;
+use visor::labels::parse_lifecycle;
+use visor::lease::Leases;
+use visor::notify::{container_name, new_notifiers};
+use visor::plan::Plan;
+use visor::pressure::PressureState;
+use visor::psutil::*;
+use visor::schedule;
+use visor::whitelist::{Entries, Kind};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(short, long, value_name = "FILE", default_value_t = String::from("config.json"))]
+    #[clap(short, long, value_name = "FILE", default_value_t = String::from("config.json"), global = true)]
     config: String,
 
-    /// Run as daemon
-    #[clap(short)]
+    /// Same as the daemon subcommand, kept for existing service files
+    #[clap(short, hide = true)]
     daemon: bool,
+
+    /// Same as run --dry-run, kept for existing scripts. Also applies to clean and stop
+    #[clap(long, hide = true)]
+    dry_run: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run every monitor step once, the default without a subcommand
+    Run {
+        /// Print the actions that would be taken without executing them
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Run the scheduled tasks until stopped, serving metrics when configured
+    Daemon,
+
+    /// Run a single cleanup step once
+    Clean {
+        #[clap(arg_enum)]
+        target: CleanTarget,
+
+        /// Print the actions that would be taken without executing them
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Stop overloaded containers once, as the resources task does
+    Stop {
+        /// Print the container that would be stopped first without stopping it
+        #[clap(long)]
+        simulate: bool,
+    },
+
+    /// Show the host usage, containers, leases and the latest actions
+    Status,
+
+    /// List the containers with their owner, deploy directory and lease
+    Instances,
+
+    /// Manage the whitelist entries added at runtime, kept in state_dir
+    Whitelist {
+        #[clap(subcommand)]
+        command: WhitelistCommand,
+    },
+
+    /// Load the config and build the notifiers, reporting the first error
+    CheckConfig,
+
+    /// Show the actions taken by visor and visor-serv, newest first
+    History {
+        /// Container ID prefix, container name, image ID, volume name or path
+        target: Option<String>,
+
+        /// Owner email
+        #[clap(long)]
+        owner: Option<String>,
+
+        /// Action such as stop_container, remove_container or notify
+        #[clap(long)]
+        action: Option<String>,
+
+        /// Only actions within this period, such as 12h or 7d
+        #[clap(long, parse(try_from_str = parse_since))]
+        since: Option<Duration>,
+
+        #[clap(long, default_value_t = 50)]
+        limit: u32,
+
+        /// Print as JSON
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum CleanTarget {
+    Images,
+    Volumes,
+    Containers,
+    Pkg,
+    Release,
+}
+
+#[derive(Subcommand, Debug)]
+enum WhitelistCommand {
+    /// Show the entries of the config and those added at runtime
+    List,
+    /// Add an exact value, glob or `re:` regex, as in the config
+    Add { kind: Kind, entry: String },
+    /// Remove an entry added at runtime, those of the config are left alone
+    Remove { kind: Kind, entry: String },
+}
+
+impl Args {
+    /// The subcommand to run with the hidden flags applied, so `--dry-run` before a
+    /// subcommand never lets it act for real.
+    fn command(&mut self) -> Result<Command> {
+        let dry_run = self.dry_run;
+        if self.daemon && dry_run {
+            return Err(anyhow!("-d cannot be combined with --dry-run"));
+        }
+        match self.command.take() {
+            None if self.daemon => Ok(Command::Daemon),
+            None => Ok(Command::Run { dry_run }),
+            Some(Command::Daemon) if dry_run => {
+                Err(anyhow!("--dry-run cannot be combined with daemon"))
+            }
+            Some(Command::Daemon) => Ok(Command::Daemon),
+            Some(_) if self.daemon => Err(anyhow!("-d cannot be combined with a subcommand")),
+            Some(Command::Run { dry_run: d }) => Ok(Command::Run {
+                dry_run: d || dry_run,
+            }),
+            Some(Command::Clean { target, dry_run: d }) => Ok(Command::Clean {
+                target,
+                dry_run: d || dry_run,
+            }),
+            Some(Command::Stop { simulate }) => Ok(Command::Stop {
+                simulate: simulate || dry_run,
+            }),
+            Some(command) => Ok(command),
+        }
+    }
+}
+
+fn parse_since(s: &str) -> Result<Duration, String> {
+    parse_lifecycle(s).ok_or_else(|| format!("invalid period: {}", s))
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     std::env::set_var("RUST_LOG", "info");
     env_logger::init();
 
-    let args = Args::parse();
-    let cfg = Config::new(&args.config).unwrap();
-
-    let notifier = WechatNotifier::new(&cfg.notify_webhook).unwrap();
-    let docker = Docker::connect_with_socket_defaults().unwrap();
-
-    let mut wechat = Wechat::new(
-        &cfg.wechat.corp_id,
-        &cfg.wechat.app_secret,
-        cfg.wechat.users.clone(),
-    )
-    .unwrap();
-    wechat
-        .map_users_by_department(cfg.wechat.department_id)
-        .await
-        .unwrap();
-    info!("Wechat users count: {}", wechat.users.len());
-
-    if args.daemon {
-        loop {
-            monitor(&cfg, &docker, &notifier, &mut wechat)
-                .await
-                .unwrap();
+    let mut args = Args::parse();
+    let command = args.command()?;
+    let cfg = Config::new(&args.config)
+        .map_err(|e| anyhow!("Load config {} failed: {}", args.config, e))?;
+
+    match command {
+        Command::Run { dry_run } => {
+            let notifier = new_notifiers(&cfg)?;
+            let docker = Docker::connect_with_socket_defaults()?;
+            let metrics = spawn_sampler(
+                Duration::from_secs(cfg.sampling.interval),
+                cfg.sampling.window,
+            )?;
+            let mut plan = Plan::new(dry_run);
+            monitor(&cfg, &docker, &notifier, &metrics, &mut plan).await?;
+            if dry_run {
+                plan.print()?;
+            }
+        }
+        Command::Daemon => {
+            let notifier = new_notifiers(&cfg)?;
+            let docker = Docker::connect_with_socket_defaults()?;
+            let metrics = spawn_sampler(
+                Duration::from_secs(cfg.sampling.interval),
+                cfg.sampling.window,
+            )?;
+            let exporter = async {
+                if let Some(exporter) = &cfg.metrics {
+                    if let Err(e) = visor::metrics::serve(&exporter.listen).await {
+                        warn!("Serve metrics failed: {}", e);
+                    }
+                }
+            };
+            let schedule =
+                schedule::run(Arc::new(cfg.clone()), docker, Arc::new(notifier), metrics);
+            tokio::join!(schedule, exporter);
+        }
+        Command::Clean { target, dry_run } => clean(&cfg, target, dry_run).await?,
+        Command::Stop { simulate } => {
+            let notifier = new_notifiers(&cfg)?;
+            let docker = Docker::connect_with_socket_defaults()?;
+            let metrics = spawn_sampler(
+                Duration::from_secs(cfg.sampling.interval),
+                cfg.sampling.window,
+            )?;
+            let mut pressure = PressureState::new(&cfg);
+            wait_for_window(&metrics, &pressure).await?;
+            let mut plan = Plan::new(simulate);
+            stop_containers(&docker, &cfg, &notifier, &metrics, &mut pressure, &mut plan).await?;
+            if simulate {
+                plan.print()?;
+            }
+        }
+        Command::Status => status(&cfg).await?,
+        Command::Instances => instances(&cfg).await?,
+        Command::Whitelist { command } => manage_whitelist(&cfg, command)?,
+        Command::CheckConfig => {
+            let notifier = new_notifiers(&cfg)?;
+            println!("Config {} is valid", args.config);
+            println!("Notifiers: {}", notifier.names().join(", "));
         }
+        Command::History {
+            target,
+            owner,
+            action,
+            since,
+            limit,
+            json,
+        } => {
+            let mut query = Query {
+                action,
+                target,
+                owner,
+                limit: Some(limit),
+                ..Default::default()
+            };
+            if let Some(since) = since {
+                query = query.since(since);
+            }
+            let records = History::open(&cfg.state_dir)?.query(&query)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&records)?);
+            } else {
+                records.iter().for_each(|r| println!("{}", r));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn clean(cfg: &Config, target: CleanTarget, dry_run: bool) -> Result<()> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let mut plan = Plan::new(dry_run);
+    match target {
+        CleanTarget::Images => {
+            let notifier = new_notifiers(cfg)?;
+            clean_images(&docker, cfg, &notifier, &mut plan).await?;
+        }
+        CleanTarget::Volumes => clean_volumes(&docker, cfg, &mut plan).await?,
+        CleanTarget::Containers => {
+            let notifier = new_notifiers(cfg)?;
+            clean_exited_containers(&docker, cfg, &notifier, &mut plan).await?;
+        }
+        CleanTarget::Pkg => {
+            clean_pkg(
+                &docker,
+                cfg.lifecycle.pkg,
+                &cfg.records_log,
+                &cfg.state_dir,
+                &mut plan,
+            )
+            .await?
+        }
+        CleanTarget::Release => {
+            clean_release(
+                &docker,
+                cfg.lifecycle.release,
+                &cfg.records_log,
+                &cfg.state_dir,
+                &mut plan,
+            )
+            .await?
+        }
+    }
+    if dry_run {
+        plan.print()?;
     }
+    Ok(())
+}
+
+async fn status(cfg: &Config) -> Result<()> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    // A short interval, so the CPU usage is known within a second
+    let metrics = spawn_sampler(Duration::from_secs(1), 1)?;
+    let sample = metrics.current().await?;
+    println!(
+        "Host: cpu {:.1}%, mem {:.1}%, disk {:.1}%",
+        sample.cpu, sample.mem, sample.disk
+    );
+    // Sustained thresholds cannot be met by a single sample, those are left to `visor stop`
+    let mut pressure = PressureState::new(cfg);
+    let samples = metrics.samples();
+    match pressure.warm_up(&samples, Instant::now()) {
+        Some(_) => {
+            println!("Pressure: sustained thresholds are not evaluated, see visor stop --simulate")
+        }
+        None => {
+            let reason = pressure.evaluate(&samples, Instant::now());
+            println!("Pressure: {}", reason.as_deref().unwrap_or("none"));
+        }
+    }
+
+    let running = list_running_containers(&docker).await?;
+    let exited = list_exited_containers(&docker).await?;
+    println!(
+        "Containers: {} running, {} exited",
+        running.len(),
+        exited.len()
+    );
+
+    let leases = Leases::load(&cfg.state_dir)?;
+    let now = Utc::now().timestamp();
+    let leased = running
+        .iter()
+        .chain(exited.iter())
+        .filter(|c| {
+            leases
+                .active(c.id.as_deref().unwrap_or_default(), now)
+                .is_some()
+        })
+        .count();
+    println!("Leases: {} active", leased);
+
+    let added = Entries::load(&cfg.state_dir)?;
+    let config = Entries::from_config(&cfg.whitelist);
+    println!(
+        "Whitelist: {} containers, {} images, {} volumes ({} added at runtime)",
+        config.containers.len() + added.containers.len(),
+        config.images.len() + added.images.len(),
+        config.volumes.len() + added.volumes.len(),
+        added.containers.len() + added.images.len() + added.volumes.len()
+    );
 
-    monitor(&cfg, &docker, &notifier, &mut wechat)
-        .await
-        .unwrap();
+    let query = Query {
+        limit: Some(5),
+        ..Default::default()
+    };
+    let records = History::open(&cfg.state_dir)?.query(&query)?;
+    if !records.is_empty() {
+        println!("Latest actions:");
+        records.iter().for_each(|r| println!("  {}", r));
+    }
+    Ok(())
+}
+
+async fn instances(cfg: &Config) -> Result<()> {
+    let docker = Docker::connect_with_socket_defaults()?;
+    let opts = ListContainersOptions::<String> {
+        all: true,
+        ..Default::default()
+    };
+    let containers = docker.list_containers(Some(opts)).await?;
+    let leases = Leases::load(&cfg.state_dir)?;
+    let now = Utc::now().timestamp();
+    for container in containers.iter() {
+        let id = container.id.clone().unwrap_or_default();
+        let instance = find_instance(&docker, container, &cfg.records_log)
+            .await
+            .unwrap_or_default();
+        let lease = leases
+            .active(&id, now)
+            .and_then(|lease| Local.timestamp_opt(lease.until, 0).single())
+            .map(|until| until.format("%Y-%m-%d %H:%M").to_string());
+        let or_dash = |s: &str| {
+            if s.is_empty() {
+                "-".to_string()
+            } else {
+                s.to_string()
+            }
+        };
+        println!(
+            "{}  {}  {}  owner {}  {}  {}  lease {}",
+            &id[..id.len().min(12)],
+            or_dash(&container_name(container)),
+            or_dash(container.state.as_deref().unwrap_or_default()),
+            or_dash(&instance.owner),
+            or_dash(&instance.deploy_dir),
+            or_dash(&instance.config.base_url),
+            lease.unwrap_or_else(|| "-".to_string())
+        );
+    }
+    Ok(())
+}
+
+fn manage_whitelist(cfg: &Config, command: WhitelistCommand) -> Result<()> {
+    let config = Entries::from_config(&cfg.whitelist);
+    let mut added = Entries::load(&cfg.state_dir)?;
+    match command {
+        WhitelistCommand::List => {
+            for kind in [Kind::Containers, Kind::Images, Kind::Volumes] {
+                println!("{}:", kind.as_str());
+                for entry in config.entries(kind) {
+                    println!("  {} (config)", entry);
+                }
+                for entry in added.entries(kind) {
+                    println!("  {}", entry);
+                }
+            }
+        }
+        WhitelistCommand::Add { kind, entry } => {
+            let entry = entry.trim();
+            if config.entries(kind).iter().any(|e| e == entry) {
+                return Err(anyhow!("{} is already in the config", entry));
+            }
+            if !added.add(kind, entry)? {
+                return Err(anyhow!("{} is already in the whitelist", entry));
+            }
+            added.save(&cfg.state_dir)?;
+            println!("Added {} to the {} whitelist", entry, kind.as_str());
+        }
+        WhitelistCommand::Remove { kind, entry } => {
+            if !added.remove(kind, &entry) {
+                return Err(if config.entries(kind).contains(&entry) {
+                    anyhow!("{} is in the config, edit the config to remove it", entry)
+                } else {
+                    anyhow!("{} is not in the whitelist", entry)
+                });
+            }
+            added.save(&cfg.state_dir)?;
+            println!("Removed {} from the {} whitelist", entry, kind.as_str());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Result<Command> {
+        let mut args = Args::try_parse_from(args)?;
+        args.command()
+    }
+
+    #[test]
+    fn test_hidden_flags() {
+        assert!(matches!(
+            command(&["visor", "--dry-run", "clean", "images"]).unwrap(),
+            Command::Clean {
+                target: CleanTarget::Images,
+                dry_run: true
+            }
+        ));
+        assert!(matches!(
+            command(&["visor", "--dry-run", "stop"]).unwrap(),
+            Command::Stop { simulate: true }
+        ));
+        assert!(matches!(
+            command(&["visor", "--dry-run"]).unwrap(),
+            Command::Run { dry_run: true }
+        ));
+        assert!(matches!(
+            command(&["visor", "clean", "volumes"]).unwrap(),
+            Command::Clean { dry_run: false, .. }
+        ));
+        assert!(matches!(
+            command(&["visor", "-d"]).unwrap(),
+            Command::Daemon
+        ));
+        assert!(command(&["visor", "-d", "clean", "images"]).is_err());
+        assert!(command(&["visor", "--dry-run", "daemon"]).is_err());
+    }
 }
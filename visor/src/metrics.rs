@@ -0,0 +1,165 @@
+use std::future::Future;
+
+use actix_web::{get, App, HttpResponse, HttpServer};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, Encoder, GaugeVec,
+    HistogramVec, IntCounterVec, TextEncoder,
+};
+
+use crate::plan::ActionKind;
+use crate::psutil::Sample;
+
+lazy_static! {
+    static ref HOST_USAGE: GaugeVec = register_gauge_vec!(
+        "visor_host_usage_percent",
+        "Latest host usage sampled by visor",
+        &["resource"]
+    )
+    .unwrap();
+    static ref ACTIONS: IntCounterVec = register_int_counter_vec!(
+        "visor_actions_total",
+        "Containers stopped or removed, images, volumes and files removed",
+        &["action", "result"]
+    )
+    .unwrap();
+    static ref RECLAIMED_BYTES: IntCounterVec = register_int_counter_vec!(
+        "visor_reclaimed_bytes_total",
+        "Disk space reclaimed by removing images and volumes of known size",
+        &["action"]
+    )
+    .unwrap();
+    static ref NOTIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        "visor_notifications_total",
+        "Notifications sent by each notifier",
+        &["notifier", "event", "result"]
+    )
+    .unwrap();
+    static ref STEP_DURATION: HistogramVec = register_histogram_vec!(
+        "visor_step_duration_seconds",
+        "Run time of each monitor step",
+        &["step"],
+        vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0]
+    )
+    .unwrap();
+    static ref STEP_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "visor_step_failures_total",
+        "Monitor steps that returned an error",
+        &["step"]
+    )
+    .unwrap();
+}
+
+fn result_label(ok: bool) -> &'static str {
+    if ok {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+pub fn observe_host(sample: &Sample) {
+    HOST_USAGE
+        .with_label_values(&["cpu"])
+        .set(sample.cpu as f64);
+    HOST_USAGE
+        .with_label_values(&["mem"])
+        .set(sample.mem as f64);
+    HOST_USAGE
+        .with_label_values(&["disk"])
+        .set(sample.disk as f64);
+}
+
+/// Counts an executed action, dry runs are not counted.
+pub fn observe_action(kind: ActionKind, ok: bool) {
+    ACTIONS
+        .with_label_values(&[kind.as_str(), result_label(ok)])
+        .inc();
+}
+
+/// Docker reports unknown sizes as -1, which are left out.
+pub fn observe_reclaimed(kind: ActionKind, bytes: i64) {
+    if bytes > 0 {
+        RECLAIMED_BYTES
+            .with_label_values(&[kind.as_str()])
+            .inc_by(bytes as u64);
+    }
+}
+
+pub fn observe_notification(notifier: &str, event: &str, ok: bool) {
+    NOTIFICATIONS
+        .with_label_values(&[notifier, event, result_label(ok)])
+        .inc();
+}
+
+/// Runs a monitor step, recording how long it took and whether it failed.
+pub async fn timed<T>(step: &str, f: impl Future<Output = Result<T>>) -> Result<T> {
+    let timer = STEP_DURATION.with_label_values(&[step]).start_timer();
+    let res = f.await;
+    timer.observe_duration();
+    if res.is_err() {
+        STEP_FAILURES.with_label_values(&[step]).inc();
+    }
+    res
+}
+
+/// The registered metrics in the Prometheus text format.
+pub fn gather() -> Result<String> {
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+#[get("/metrics")]
+async fn metrics() -> HttpResponse {
+    match gather() {
+        Ok(s) => HttpResponse::Ok()
+            .content_type(prometheus::TEXT_FORMAT)
+            .body(s),
+        Err(e) => {
+            warn!("Gather metrics failed: {}", e);
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+/// Serves `/metrics` until the daemon exits.
+pub async fn serve(listen: &str) -> Result<()> {
+    info!("Serving metrics on http://{}/metrics", listen);
+    // Stopping is left to the daemon
+    HttpServer::new(|| App::new().service(metrics))
+        .workers(1)
+        .disable_signals()
+        .bind(listen)?
+        .run()
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[tokio::test]
+    async fn test_gather() {
+        observe_action(ActionKind::RemoveImage, true);
+        observe_reclaimed(ActionKind::RemoveImage, 1024);
+        observe_reclaimed(ActionKind::RemoveVolume, -1);
+        observe_notification("wechat", "container_stopped", false);
+        timed("clean_images", async { Ok(()) }).await.unwrap();
+        let _ = timed::<()>("clean_volumes", async { Err(anyhow!("docker is down")) }).await;
+
+        let s = gather().unwrap();
+        assert!(s.contains(r#"visor_actions_total{action="remove_image",result="success"} 1"#));
+        assert!(s.contains(r#"visor_reclaimed_bytes_total{action="remove_image"} 1024"#));
+        assert!(!s.contains(r#"visor_reclaimed_bytes_total{action="remove_volume"}"#));
+        assert!(s.contains(
+            r#"visor_notifications_total{event="container_stopped",notifier="wechat",result="failure"} 1"#
+        ));
+        assert!(s.contains(r#"visor_step_duration_seconds_count{step="clean_images"} 1"#));
+        assert!(s.contains(r#"visor_step_failures_total{step="clean_volumes"} 1"#));
+    }
+}
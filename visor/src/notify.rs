@@ -1,41 +1,400 @@
 use crate::{
-    config::Config,
+    config::{self, Config, SmtpSecurity},
+    dingtalk::robot as dingtalk,
+    event::{Event, EventKind},
+    feishu::robot as feishu,
+    history::{self, Record},
+    lease::DEFAULT_EXTEND_DAYS,
+    link,
+    locale::Locale,
+    metrics,
+    psutil::Sample,
+    slack::incoming_webhook::{self as slack, IncomingWebhook},
+    template::{self, Format, Templates},
     wechat::group_robot::{GroupRobot, Markdown, Message, Text},
+    wechat::wechat::Wechat,
 };
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use bollard::models::ContainerSummary;
+use chrono::Local;
+use hmac::{Hmac, Mac};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use log::{info, warn};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::StatusCode;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::{get_cpu_usage, get_disk_usage, get_mem_usage, parse_status_time, Instance};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time;
 
 // 群机器人配置说明 https://developer.work.weixin.qq.com/document/path/91770
 
+/// Each notifier renders the event in its own format and resolves the
+/// recipients from the owner email.
 #[async_trait]
-pub trait Notifier {
-    async fn notify(&self, msg: &str, user_id: Option<&String>) -> Result<()>;
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, event: &Event) -> Result<()>;
+
+    /// Whether events of `kind` are sent at all, skipped ones are left out
+    /// of the metrics and the history.
+    fn accepts(&self, _kind: EventKind) -> bool {
+        true
+    }
+}
+
+/// Sends every notification through all the configured notifiers.
+pub struct Notifiers {
+    /// Named for the metrics and the history
+    notifiers: Vec<(&'static str, Box<dyn Notifier>)>,
+    state_dir: String,
+}
+
+impl Notifiers {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.notifiers.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+#[async_trait]
+impl Notifier for Notifiers {
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let mut errors = Vec::new();
+        let payload = event.payload();
+        let subject = payload
+            .container
+            .as_ref()
+            .map(|c| c.name.as_str())
+            .or_else(|| payload.image.as_ref().map(|i| i.id.as_str()))
+            .unwrap_or_default();
+        for (name, notifier) in self.notifiers.iter() {
+            if !notifier.accepts(event.kind()) {
+                continue;
+            }
+            let res = notifier.notify(event).await;
+            metrics::observe_notification(name, event.kind().as_str(), res.is_ok());
+            let mut record = Record::new(history::NOTIFY, name, event.kind().as_str())
+                .name(subject)
+                .owner(payload.owner.clone())
+                .result(&res);
+            if let Some(sample) = &payload.metrics {
+                record = record.metrics(sample);
+            }
+            history::record(&self.state_dir, record).await;
+            if let Err(e) = res {
+                errors.push(e.to_string());
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} notifier(s) failed: {}",
+                errors.len(),
+                errors.join("; ")
+            ))
+        }
+    }
+}
+
+/// Builds the notifiers enabled in the config, at least one is required.
+/// Sections with an empty webhook are skipped.
+pub fn new_notifiers(cfg: &Config) -> Result<Notifiers> {
+    let renderer = Renderer::new(cfg)?;
+    let mut notifiers: Vec<(&'static str, Box<dyn Notifier>)> = Vec::new();
+    if !cfg.notify_webhook.is_empty() {
+        notifiers.push((
+            "wechat",
+            Box::new(WechatNotifier::new(
+                &cfg.notify_webhook,
+                &cfg.wechat,
+                renderer.clone(),
+            )?),
+        ));
+    }
+    if let Some(slack) = cfg.slack.as_ref().filter(|s| !s.webhook.is_empty()) {
+        notifiers.push((
+            "slack",
+            Box::new(SlackNotifier::new(
+                &slack.webhook,
+                slack.users.clone(),
+                renderer.clone(),
+            )?),
+        ));
+    }
+    if let Some(robot) = cfg.dingtalk.as_ref().filter(|r| !r.webhook.is_empty()) {
+        notifiers.push((
+            "dingtalk",
+            Box::new(DingtalkNotifier::new(
+                &robot.webhook,
+                robot.secret.clone().filter(|s| !s.is_empty()),
+                robot.users.clone(),
+                renderer.clone(),
+            )?),
+        ));
+    }
+    if let Some(robot) = cfg.feishu.as_ref().filter(|r| !r.webhook.is_empty()) {
+        notifiers.push((
+            "feishu",
+            Box::new(FeishuNotifier::new(
+                &robot.webhook,
+                robot.secret.clone().filter(|s| !s.is_empty()),
+                robot.users.clone(),
+                renderer.clone(),
+            )?),
+        ));
+    }
+
+    if let Some(email) = cfg.email.as_ref().filter(|e| !e.host.is_empty()) {
+        notifiers.push(("email", Box::new(EmailNotifier::new(email, renderer.clone())?)));
+    }
+    for webhook in cfg.webhooks.iter() {
+        notifiers.push(("webhook", Box::new(WebhookNotifier::new(webhook)?)));
+    }
+
+    if notifiers.is_empty() {
+        Err(anyhow!("No notifier configured"))
+    } else {
+        Ok(Notifiers {
+            notifiers,
+            state_dir: cfg.state_dir.clone(),
+        })
+    }
 }
 
+/// Renders events meant for people in the format of each notifier, image removals
+/// are left to webhooks.
 #[derive(Debug, Clone)]
+pub struct Renderer {
+    templates: Arc<Templates>,
+    serv_url: String,
+    serv_secret: String,
+    link_ttl: u64,
+    container_lifecycle: u64,
+    locale: Locale,
+    owner_locales: HashMap<String, Locale>,
+}
+
+impl Renderer {
+    pub fn new(cfg: &Config) -> Result<Self> {
+        let templates = match &cfg.templates.compiled {
+            Some(templates) => templates.clone(),
+            None => Arc::new(Templates::load(&cfg.templates)?),
+        };
+        Ok(Self {
+            templates,
+            serv_url: cfg.serv_url.clone(),
+            serv_secret: cfg.serv_secret.clone(),
+            link_ttl: cfg.link_ttl,
+            container_lifecycle: cfg.lifecycle.container,
+            locale: cfg.locale,
+            owner_locales: cfg.owner_locales.clone(),
+        })
+    }
+
+    /// The owner's own locale if configured, else the deployment's.
+    pub fn locale(&self, owner: Option<&str>) -> Locale {
+        owner
+            .and_then(|owner| self.owner_locales.get(owner))
+            .copied()
+            .unwrap_or(self.locale)
+    }
+
+    /// Signed link to a visor-serv page acting on the container, empty without `serv_url`.
+    fn action_url(&self, action: &str, container_id: &str, locale: Locale) -> String {
+        if self.serv_url.is_empty() {
+            String::new()
+        } else {
+            link::signed_url(
+                &self.serv_url,
+                &self.serv_secret,
+                action,
+                container_id,
+                Local::now().timestamp() + self.link_ttl as i64,
+                locale,
+            )
+        }
+    }
+
+    /// Signed link extending the lease by the default days, empty without `serv_url`.
+    fn extend_url(&self, container_id: &str, locale: Locale) -> String {
+        if self.serv_url.is_empty() {
+            String::new()
+        } else {
+            link::extend_url(
+                &self.serv_url,
+                &self.serv_secret,
+                container_id,
+                DEFAULT_EXTEND_DAYS,
+                Local::now().timestamp() + self.link_ttl as i64,
+                locale,
+            )
+        }
+    }
+
+    /// Whether `kind` has a template, `image_removed` is only sent to webhooks.
+    pub fn renders(&self, kind: EventKind) -> bool {
+        kind != EventKind::ImageRemoved
+    }
+
+    /// Renders the event in `format`, `None` if its kind has no template.
+    pub fn render(&self, event: &Event, format: Format) -> Result<Option<String>> {
+        let kind = event.kind();
+        let owner = event.owner();
+        let locale = self.locale(owner.as_deref());
+        let res = match event {
+            Event::ContainerStopped {
+                container,
+                instance,
+                metrics,
+                reason,
+                running_time,
+            } => {
+                let container_id = short_id(container);
+                let restart_url = self.action_url(link::START_CONTAINER, &container_id, locale);
+                self.templates.render(
+                    kind,
+                    locale,
+                    format,
+                    &template::ContainerStopped {
+                        base_url: instance.config.base_url.clone(),
+                        owner: instance.owner.clone(),
+                        running_time: locale.format_duration(*running_time),
+                        container_id,
+                        container_name: container_name(container),
+                        deploy_dir: instance.deploy_dir.clone(),
+                        volume: instance.config.volume.clone(),
+                        metrics: template_metrics(metrics),
+                        restart_url,
+                        lifecycle: self.container_lifecycle,
+                        reason: reason.clone(),
+                    },
+                )
+            }
+            Event::ContainerExpiring {
+                container,
+                instance,
+                remaining,
+            } => {
+                let container_id = short_id(container);
+                let keep_url = self.extend_url(&container_id, locale);
+                self.templates.render(
+                    kind,
+                    locale,
+                    format,
+                    &template::ContainerExpiring {
+                        base_url: instance.config.base_url.clone(),
+                        owner: owner.unwrap_or_default(),
+                        container_id,
+                        container_name: container_name(container),
+                        deploy_dir: instance.deploy_dir.clone(),
+                        remaining: locale.format_duration(*remaining),
+                        keep_url,
+                    },
+                )
+            }
+            Event::ContainerRemoved { container, reason } => self.templates.render(
+                kind,
+                locale,
+                format,
+                &template::ContainerRemoved {
+                    owner: owner.unwrap_or_default(),
+                    container_id: short_id(container),
+                    container_name: container_name(container),
+                    image: container.image.clone().unwrap_or_default(),
+                    reason: reason.clone(),
+                },
+            ),
+            Event::ThresholdExceeded { metrics, reason } => self.templates.render(
+                kind,
+                locale,
+                format,
+                &template::ThresholdExceeded {
+                    host: hostname::get()
+                        .map(|h| h.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    metrics: template_metrics(metrics),
+                    reason: reason.clone(),
+                },
+            ),
+            Event::ImageRemoved { id, .. } => {
+                info!(
+                    "Ignored: {} of {} is only sent to webhooks",
+                    kind.as_str(),
+                    id
+                );
+                return Ok(None);
+            }
+        };
+        res.map(Some)
+            .map_err(|e| anyhow!("Render {} failed: {}", kind.as_str(), e))
+    }
+}
+
+fn template_metrics(sample: &Sample) -> template::Metrics {
+    template::Metrics {
+        cpu: sample.cpu as i32,
+        mem: sample.mem as i32,
+        disk: sample.disk as i32,
+    }
+}
+
+fn short_id(container: &ContainerSummary) -> String {
+    let mut id = container.id.clone().unwrap_or_default();
+    id.truncate(12);
+    id
+}
+
+pub fn container_name(container: &ContainerSummary) -> String {
+    container
+        .names
+        .as_ref()
+        .and_then(|names| names.first())
+        .map(|name| name.trim_start_matches('/').to_string())
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
 pub struct WechatNotifier {
     webhook: String,
+    renderer: Renderer,
+    /// email => WeCom user ID, from the config
+    users: HashMap<String, String>,
+    /// Looks up unknown owners in the WeCom department, absent without app credentials
+    directory: Option<Mutex<Wechat>>,
+    department_id: u32,
 }
 
 #[async_trait]
 impl Notifier for WechatNotifier {
-    async fn notify(&self, msg: &str, user_id: Option<&String>) -> Result<()> {
-        let group = GroupRobot::new(self.webhook.clone())?;
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.renderer.renders(kind)
+    }
 
-        let m = Markdown {
-            content: msg.to_string(),
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let msg = match self.renderer.render(event, Format::Wecom)? {
+            Some(msg) => msg,
+            None => return Ok(()),
         };
+        let group = GroupRobot::new(self.webhook.clone())?;
+
+        let m = Markdown { content: msg };
         let markdown = Message::markdown(&m);
         group.send_message(&markdown).await?;
 
-        if user_id.is_some() {
-            let mentioned_list = user_id.map(|id| vec![id.clone()]);
+        let user_id = match event.owner() {
+            Some(owner) => self.user_id(&owner).await,
+            None => None,
+        };
+        if let Some(user_id) = &user_id {
             let t = &Text {
                 content: "".to_string(),
-                mentioned_list,
+                mentioned_list: Some(vec![user_id.clone()]),
                 mentioned_mobile_list: None,
             };
             let text = Message::text(&t);
@@ -46,69 +405,799 @@ impl Notifier for WechatNotifier {
 }
 
 impl WechatNotifier {
-    pub fn new(webhook: &str) -> Result<Self> {
+    pub fn new(webhook: &str, cfg: &config::Wechat, renderer: Renderer) -> Result<Self> {
+        if webhook.is_empty() {
+            return Err(anyhow!("Webhook is empty"));
+        }
+        let directory = match Wechat::new(&cfg.corp_id, &cfg.app_secret, HashMap::new()) {
+            Ok(wechat) => Some(Mutex::new(wechat)),
+            Err(e) => {
+                warn!("WeCom user lookup disabled: {}", e);
+                None
+            }
+        };
+        Ok(Self {
+            webhook: webhook.to_string(),
+            renderer,
+            users: cfg.users.clone(),
+            directory,
+            department_id: cfg.department_id,
+        })
+    }
+
+    async fn user_id(&self, email: &str) -> Option<String> {
+        if let Some(user_id) = self.users.get(email) {
+            return Some(user_id.clone());
+        }
+        let mut wechat = self.directory.as_ref()?.lock().await;
+        if !wechat.users.contains_key(email) {
+            match wechat.map_users_by_department(self.department_id).await {
+                Ok(()) => info!("Wechat users count: {}", wechat.users.len()),
+                Err(e) => warn!("Map wechat users failed: {}", e),
+            }
+        }
+        wechat.users.get(email).cloned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlackNotifier {
+    webhook: String,
+    /// email => Slack member ID
+    users: HashMap<String, String>,
+    renderer: Renderer,
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.renderer.renders(kind)
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let text = match self.renderer.render(event, Format::Mrkdwn)? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let webhook = IncomingWebhook::new(self.webhook.clone())?;
+
+        let mut blocks = vec![slack::Block::section(&text)];
+        if let Some(user_id) = event.owner().and_then(|owner| self.users.get(&owner)) {
+            blocks.push(slack::Block::Context {
+                elements: vec![slack::TextObject::Mrkdwn {
+                    text: format!("<@{}>", user_id),
+                }],
+            });
+        }
+        let message = slack::Message {
+            text: text.lines().next().unwrap_or_default().to_string(),
+            blocks,
+        };
+        webhook.send_message(&message).await
+    }
+}
+
+impl SlackNotifier {
+    pub fn new(webhook: &str, users: HashMap<String, String>, renderer: Renderer) -> Result<Self> {
         if webhook.is_empty() {
-            Err(anyhow!("Webhook is empty"))
+            Err(anyhow!("Slack webhook is empty"))
         } else {
             Ok(Self {
                 webhook: webhook.to_string(),
+                users,
+                renderer,
             })
         }
     }
 }
 
-pub fn message_tpl(container: &ContainerSummary, inst: &Instance, cfg: &Config) -> String {
-    let mut container_id = container.id.clone().unwrap_or_default();
-    container_id.truncate(12);
-
-    let items = parse_status_time(&container.status.clone().unwrap_or_default());
-    let running_time = format!("{} {}", items[0], items[1]);
-
-    let cpu_usage = get_cpu_usage().unwrap();
-    let mem_usage = get_mem_usage().unwrap();
-    let disk_usage = get_disk_usage().unwrap();
-    let mut s = format!(
-        r##"由于私有部署环境资源使用达到上限，以下容器已被强制停止:
-> 访问地址: [{}]({})
-> 创建者: <font color="comment">{}</font>
-> 运行时长: <font color="comment">{}</font>
-> 容器ID: <font color="comment">{}</font>
-> 部署目录: <font color="comment">{}</font>
-> 数据卷: <font color="comment>{}</font>
-
-当前资源使用情况:
-> CPU: <font color="comment">{}%</font>
-> 内存: <font color="comment">{}%</font>
-> 磁盘: <font color="comment">{}%</font>
-
-如需继续使用该实例，可自行重启容器:
-> 重启命令: <font color="comment">docker start {}</font>"##,
-        inst.config.base_url,
-        inst.config.base_url,
-        inst.owner,
-        running_time,
-        container_id,
-        inst.deploy_dir,
-        inst.config.volume,
-        cpu_usage as i32,
-        mem_usage as i32,
-        disk_usage as i32,
-        container_id
-    );
-
-    if !cfg.serv_url.is_empty() {
-        s = format!(
-            r##"{}
-> 重启链接: [点击启动]({})"##,
-            s,
-            format!("{}/start_container/{}", cfg.serv_url, container_id)
-        )
+#[derive(Debug, Clone)]
+pub struct DingtalkNotifier {
+    webhook: String,
+    secret: Option<String>,
+    /// email => DingTalk user ID
+    users: HashMap<String, String>,
+    renderer: Renderer,
+}
+
+#[async_trait]
+impl Notifier for DingtalkNotifier {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.renderer.renders(kind)
     }
 
-    format!(
-        r##"{}
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let mut text = match self.renderer.render(event, Format::Markdown)? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let robot = dingtalk::Robot::new(self.webhook.clone(), self.secret.clone())?;
 
-<font color="warning">注意: 实例如不再使用，将在 {} 天后被删除！</font>"##,
-        s, cfg.lifecycle.container,
+        let mut at = dingtalk::At::default();
+        if let Some(user_id) = event.owner().and_then(|owner| self.users.get(&owner)) {
+            text = format!("{}\n\n@{}", text, user_id);
+            at.at_user_ids.push(user_id.clone());
+        }
+        let m = dingtalk::Markdown {
+            title: text.lines().next().unwrap_or_default().to_string(),
+            text,
+        };
+        robot
+            .send_message(&dingtalk::Message::markdown(&m, &at))
+            .await
+    }
+}
+
+impl DingtalkNotifier {
+    pub fn new(
+        webhook: &str,
+        secret: Option<String>,
+        users: HashMap<String, String>,
+        renderer: Renderer,
+    ) -> Result<Self> {
+        if webhook.is_empty() {
+            Err(anyhow!("DingTalk webhook is empty"))
+        } else {
+            Ok(Self {
+                webhook: webhook.to_string(),
+                secret,
+                users,
+                renderer,
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeishuNotifier {
+    webhook: String,
+    secret: Option<String>,
+    /// email => Feishu open ID
+    users: HashMap<String, String>,
+    renderer: Renderer,
+}
+
+#[async_trait]
+impl Notifier for FeishuNotifier {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.renderer.renders(kind)
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let mut content = match self.renderer.render(event, Format::Markdown)? {
+            Some(content) => content,
+            None => return Ok(()),
+        };
+        let robot = feishu::Robot::new(self.webhook.clone(), self.secret.clone())?;
+
+        // Feishu resolves tenant members by email when no open ID is configured
+        if let Some(owner) = event.owner() {
+            match self.users.get(&owner) {
+                Some(open_id) => content = format!("{}\n\n<at id={}></at>", content, open_id),
+                None => content = format!("{}\n\n<at email={}></at>", content, owner),
+            }
+        }
+        robot
+            .send_message(feishu::Message::markdown(&content))
+            .await
+    }
+}
+
+impl FeishuNotifier {
+    pub fn new(
+        webhook: &str,
+        secret: Option<String>,
+        users: HashMap<String, String>,
+        renderer: Renderer,
+    ) -> Result<Self> {
+        if webhook.is_empty() {
+            Err(anyhow!("Feishu webhook is empty"))
+        } else {
+            Ok(Self {
+                webhook: webhook.to_string(),
+                secret,
+                users,
+                renderer,
+            })
+        }
+    }
+}
+
+/// SMTP transport and sender built from the `email` section, also used by visor-serv.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(cfg: &config::Email) -> Result<Self> {
+        let builder = match cfg.security {
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&cfg.host)
+            }
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&cfg.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&cfg.host)?,
+        };
+        let mut builder = builder.port(cfg.port);
+        if let Some(username) = cfg.username.as_ref().filter(|s| !s.is_empty()) {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                cfg.password.clone().unwrap_or_default(),
+            ));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: parse_mailbox(&cfg.from)?,
+        })
+    }
+
+    /// Sends a multipart message, mail clients pick the plain or HTML part.
+    pub async fn send(
+        &self,
+        to: &[Mailbox],
+        cc: &[Mailbox],
+        subject: &str,
+        plain: String,
+        html: String,
+    ) -> Result<()> {
+        let mut builder = lettre::Message::builder()
+            .from(self.from.clone())
+            .subject(subject);
+        for m in to {
+            builder = builder.to(m.clone());
+        }
+        for m in cc {
+            builder = builder.cc(m.clone());
+        }
+        let email = builder.multipart(MultiPart::alternative_plain_html(plain, html))?;
+        self.transport.send(email).await?;
+        Ok(())
+    }
+}
+
+pub struct EmailNotifier {
+    mailer: Mailer,
+    cc: Vec<Mailbox>,
+    fallback: Vec<Mailbox>,
+    renderer: Renderer,
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.renderer.renders(kind)
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let (plain, html) = match (
+            self.renderer.render(event, Format::Plain)?,
+            self.renderer.render(event, Format::Html)?,
+        ) {
+            (Some(plain), Some(html)) => (plain, html),
+            _ => return Ok(()),
+        };
+        let owner = event.owner().unwrap_or_default();
+        let to = match owner.parse::<Mailbox>() {
+            Ok(owner) => vec![owner],
+            Err(e) => {
+                if !owner.is_empty() {
+                    warn!("Invalid owner email {}: {}", owner, e);
+                }
+                self.fallback.clone()
+            }
+        };
+        if to.is_empty() {
+            warn!("Ignored: no email recipient for notification");
+            return Ok(());
+        }
+
+        let subject = plain
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches([':', '：'])
+            .to_string();
+        self.mailer
+            .send(&to, &self.cc, &subject, plain, html_document(&html))
+            .await
+    }
+}
+
+impl EmailNotifier {
+    pub fn new(cfg: &config::Email, renderer: Renderer) -> Result<Self> {
+        Ok(Self {
+            mailer: Mailer::new(cfg)?,
+            cc: cfg
+                .cc
+                .iter()
+                .map(|s| parse_mailbox(s))
+                .collect::<Result<_>>()?,
+            fallback: cfg
+                .fallback
+                .iter()
+                .map(|s| parse_mailbox(s))
+                .collect::<Result<_>>()?,
+            renderer,
+        })
+    }
+}
+
+pub fn parse_mailbox(s: &str) -> Result<Mailbox> {
+    s.parse::<Mailbox>()
+        .map_err(|e| anyhow!("Invalid email address {}: {}", s, e))
+}
+
+/// Wraps the body rendered in `Format::Html`, which keeps the lines of the template.
+pub fn html_document(body: &str) -> String {
+    format!(
+        "<html>\n<body style=\"font-family:sans-serif;font-size:14px;white-space:pre-wrap\">{}</body>\n</html>\n",
+        body
     )
 }
+
+/// Delay before the first retry, doubled on each further attempt.
+const WEBHOOK_BACKOFF: Duration = Duration::from_secs(1);
+
+/// POSTs every event as JSON, see `crate::event::Payload` for the schema.
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    headers: HeaderMap,
+    events: Vec<EventKind>,
+    retries: u32,
+    backoff: Duration,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn accepts(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+
+    async fn notify(&self, event: &Event) -> Result<()> {
+        let kind = event.kind();
+        if !self.accepts(kind) {
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(&event.payload())?;
+        let mut attempt = 0;
+        loop {
+            let (err, retryable) = match self.post(kind, &body).await {
+                Ok(status) if status.is_success() => return Ok(()),
+                Ok(status) => (
+                    anyhow!("Webhook {} responded {}", self.url, status),
+                    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                ),
+                Err(e) => (anyhow!("Webhook {} failed: {}", self.url, e), true),
+            };
+            if !retryable || attempt >= self.retries {
+                return Err(err);
+            }
+            attempt += 1;
+            warn!("{}, retrying ({}/{})", err, attempt, self.retries);
+            time::sleep(self.backoff * 2u32.pow(attempt - 1)).await;
+        }
+    }
+}
+
+impl WebhookNotifier {
+    pub fn new(cfg: &config::Webhook) -> Result<Self> {
+        if cfg.url.is_empty() {
+            return Err(anyhow!("Webhook url is empty"));
+        }
+        let mut headers = HeaderMap::new();
+        for (k, v) in cfg.headers.iter() {
+            let name = HeaderName::from_bytes(k.as_bytes())
+                .map_err(|e| anyhow!("Invalid webhook header {}: {}", k, e))?;
+            let value = HeaderValue::from_str(v)
+                .map_err(|e| anyhow!("Invalid webhook header {}: {}", k, e))?;
+            headers.insert(name, value);
+        }
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(cfg.timeout))
+                .build()?,
+            url: cfg.url.clone(),
+            secret: cfg.secret.clone().filter(|s| !s.is_empty()),
+            headers,
+            events: cfg.events.clone(),
+            retries: cfg.retries,
+            backoff: WEBHOOK_BACKOFF,
+        })
+    }
+
+    async fn post(&self, kind: EventKind, body: &[u8]) -> Result<StatusCode> {
+        let timestamp = Local::now().timestamp().to_string();
+        let mut req = self
+            .client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Visor-Event", kind.as_str())
+            .header("X-Visor-Timestamp", &timestamp);
+        if let Some(secret) = &self.secret {
+            req = req.header(
+                "X-Visor-Signature",
+                format!("sha256={}", sign_payload(secret, &timestamp, body)?),
+            );
+        }
+        Ok(req.body(body.to_vec()).send().await?.status())
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`.
+pub fn sign_payload(secret: &str, timestamp: &str, body: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    Ok(mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EVENT_VERSION;
+    use crate::instance::{Instance, InstanceConfig};
+    use crate::psutil::Sample;
+    use crate::testutil::{serve, serve_once, smtp_sink};
+    use regex::Regex;
+
+    fn renderer() -> Renderer {
+        Renderer {
+            templates: Arc::new(Templates::load(&config::Templates::default()).unwrap()),
+            serv_url: String::new(),
+            serv_secret: "secret".to_string(),
+            link_ttl: 3600,
+            container_lifecycle: 7,
+            locale: Locale::Zh,
+            owner_locales: HashMap::new(),
+        }
+    }
+
+    fn container() -> ContainerSummary {
+        ContainerSummary {
+            id: Some("abc123".to_string()),
+            names: Some(vec!["/project-a".to_string()]),
+            image: Some("ones/project:dev".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn stopped(owner: &str) -> Event {
+        Event::ContainerStopped {
+            container: container(),
+            instance: Instance {
+                owner: owner.to_string(),
+                deploy_dir: "/data/ones/pkg/project-a".to_string(),
+                config: InstanceConfig {
+                    base_url: "https://dev-1:8443".to_string(),
+                    volume: "/data/ones/volumes/project-a".to_string(),
+                },
+            },
+            metrics: Sample {
+                cpu: 95.0,
+                mem: 60.0,
+                disk: 70.0,
+            },
+            reason: "cpu 95.0% >= 90.0%".to_string(),
+            running_time: Duration::from_secs(3600),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dingtalk_mention() {
+        let (url, handle) = serve_once(r#"{"errcode":0,"errmsg":"ok"}"#).await;
+        let mut users = HashMap::new();
+        users.insert("a@b.com".to_string(), "user1".to_string());
+        let notifier = DingtalkNotifier::new(&url, None, users, renderer()).unwrap();
+        notifier.notify(&stopped("a@b.com")).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&handle.await.unwrap().body).unwrap();
+        assert_eq!(
+            body["markdown"]["title"],
+            "由于私有部署环境资源使用达到上限，以下容器已被强制停止:"
+        );
+        let text = body["markdown"]["text"].as_str().unwrap();
+        assert!(text.contains("> 创建者: a@b.com\n"));
+        assert!(text.ends_with("\n\n@user1"));
+        assert_eq!(body["at"]["atUserIds"][0], "user1");
+    }
+
+    #[tokio::test]
+    async fn test_slack_mention() {
+        let (url, handle) = serve_once("ok").await;
+        let mut users = HashMap::new();
+        users.insert("a@b.com".to_string(), "U123".to_string());
+        let notifier = SlackNotifier::new(&url, users, renderer()).unwrap();
+        notifier.notify(&stopped("a@b.com")).await.unwrap();
+
+        let body: serde_json::Value = serde_json::from_str(&handle.await.unwrap().body).unwrap();
+        assert_eq!(
+            body["text"],
+            "由于私有部署环境资源使用达到上限，以下容器已被强制停止:"
+        );
+        let blocks = body["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["type"], "section");
+        assert_eq!(blocks[0]["text"]["type"], "mrkdwn");
+        let text = blocks[0]["text"]["text"].as_str().unwrap();
+        assert!(text.contains("a@b.com"));
+        assert_eq!(
+            blocks[1],
+            serde_json::json!({
+                "type": "context",
+                "elements": [{"type": "mrkdwn", "text": "<@U123>"}]
+            })
+        );
+
+        // No mention for owners without a Slack user, errors are reported from the body
+        let (url, handle) = serve(vec![(400, "invalid_blocks")]).await;
+        let notifier = SlackNotifier::new(&url, HashMap::new(), renderer()).unwrap();
+        let err = notifier.notify(&stopped("c@d.com")).await.unwrap_err();
+        assert!(err.to_string().contains("invalid_blocks"));
+        let requests = handle.await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["blocks"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_owner_locale() {
+        let mut renderer = renderer();
+        renderer.serv_url = "https://visor".to_string();
+        renderer
+            .owner_locales
+            .insert("en@b.com".to_string(), Locale::En);
+
+        let msg = renderer
+            .render(&stopped("a@b.com"), Format::Wecom)
+            .unwrap()
+            .unwrap();
+        assert!(msg.contains("> 运行时长: <font color=\"comment\">1 小时 0 分钟</font>"));
+        assert!(msg.contains("(https://visor/start_container/abc123?lang=zh&exp="));
+
+        let msg = renderer
+            .render(&stopped("en@b.com"), Format::Wecom)
+            .unwrap()
+            .unwrap();
+        assert!(msg.contains("> Running time: <font color=\"comment\">1 hour 0 minutes</font>"));
+        assert!(msg.contains("(https://visor/start_container/abc123?lang=en&exp="));
+    }
+
+    #[test]
+    fn test_render_expiring() {
+        let mut renderer = renderer();
+        renderer.serv_url = "https://visor".to_string();
+        let event = Event::ContainerExpiring {
+            container: ContainerSummary {
+                labels: Some(HashMap::from([(
+                    "visor.owner".to_string(),
+                    "a@b.com".to_string(),
+                )])),
+                ..container()
+            },
+            instance: Instance::default(),
+            remaining: Duration::from_secs(86400 + 3600),
+        };
+        let msg = renderer.render(&event, Format::Wecom).unwrap().unwrap();
+        let url = Regex::new(r"https://visor/extend/abc123\?lang=zh&days=7&exp=(\d+)&sig=(\w+)")
+            .unwrap()
+            .captures(&msg)
+            .unwrap();
+        let exp: i64 = url[1].parse().unwrap();
+        assert!((exp - Local::now().timestamp() - 3600).abs() < 5);
+        assert_eq!(&url[2], link::sign("secret", link::EXTEND, "abc123:7", exp));
+        let expected = r#"以下已停止的容器即将被删除:
+> 创建者: <font color="comment">a@b.com</font>
+> 容器ID: <font color="comment">abc123</font>
+> 容器名称: <font color="comment">project-a</font>
+
+如需保留该实例，可重新启动容器或延长保留期限:
+> 重启命令: <font color="comment">docker start abc123</font>
+> 保留链接: [点击保留]({url})
+
+<font color="warning">注意: 容器将在 1 天 1 小时 后被删除！</font>"#;
+        assert_eq!(msg, expected.replace("{url}", &url[0]));
+    }
+
+    #[tokio::test]
+    async fn test_unrendered_event() {
+        // Image removals are only meant for webhooks, nothing is sent
+        let notifier =
+            DingtalkNotifier::new("http://127.0.0.1:9", None, HashMap::new(), renderer()).unwrap();
+        let event = Event::ImageRemoved {
+            id: "sha256:abc".to_string(),
+            tags: vec![],
+            reason: "expired".to_string(),
+        };
+        assert!(!notifier.accepts(event.kind()));
+        assert!(renderer()
+            .render(&event, Format::Markdown)
+            .unwrap()
+            .is_none());
+        notifier.notify(&event).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_render_failed() {
+        // Passes the check on the empty variables, fails on a real event
+        let path = std::env::temp_dir().join("visor_render_failed.md");
+        std::fs::write(&path, "{% if reason %}{{ unknown_var }}{% endif %}").unwrap();
+        let cfg = config::Templates {
+            container_stopped: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let renderer = Renderer {
+            templates: Arc::new(Templates::load(&cfg).unwrap()),
+            ..renderer()
+        };
+        std::fs::remove_file(&path).unwrap();
+        assert!(renderer.render(&stopped("a@b.com"), Format::Wecom).is_err());
+        let notifier =
+            DingtalkNotifier::new("http://127.0.0.1:9", None, HashMap::new(), renderer).unwrap();
+        assert!(notifier.notify(&stopped("a@b.com")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_event() {
+        let (url, handle) = serve(vec![(500, "{}"), (200, "{}")]).await;
+        let mut headers = HashMap::new();
+        headers.insert("Authorization".to_string(), "Bearer token".to_string());
+        let mut notifier = WebhookNotifier::new(&config::Webhook {
+            url: format!("{}/hooks/visor", url),
+            secret: Some("secret".to_string()),
+            headers,
+            events: vec![EventKind::ContainerStopped],
+            retries: 1,
+            timeout: 5,
+        })
+        .unwrap();
+        notifier.backoff = Duration::from_millis(10);
+
+        // Filtered out, no request is made
+        let event = Event::ImageRemoved {
+            id: "sha256:abc".to_string(),
+            tags: vec![],
+            reason: "expired".to_string(),
+        };
+        notifier.notify(&event).await.unwrap();
+
+        notifier.notify(&stopped("a@b.com")).await.unwrap();
+
+        let requests = handle.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let req = &requests[1];
+        assert_eq!(req.request_line, "POST /hooks/visor HTTP/1.1");
+        assert_eq!(req.headers["authorization"], "Bearer token");
+        assert_eq!(req.headers["x-visor-event"], "container_stopped");
+        let signature = sign_payload(
+            "secret",
+            &req.headers["x-visor-timestamp"],
+            req.body.as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            req.headers["x-visor-signature"],
+            format!("sha256={}", signature)
+        );
+
+        let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+        assert_eq!(body["version"], EVENT_VERSION);
+        assert_eq!(body["kind"], "container_stopped");
+        assert_eq!(body["container"]["id"], "abc123");
+        assert_eq!(body["container"]["name"], "project-a");
+        assert_eq!(body["reason"], "cpu 95.0% >= 90.0%");
+        assert_eq!(body["owner"], "a@b.com");
+        assert_eq!(body["instance"]["deploy_dir"], "/data/ones/pkg/project-a");
+        assert_eq!(body["metrics"]["cpu"], 95.0);
+        assert!(body.get("image").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_client_error() {
+        let (url, handle) = serve(vec![(400, "{}")]).await;
+        let notifier = WebhookNotifier::new(&config::Webhook {
+            url,
+            secret: None,
+            headers: HashMap::new(),
+            events: vec![],
+            retries: 3,
+            timeout: 5,
+        })
+        .unwrap();
+        let event = Event::ThresholdExceeded {
+            metrics: Sample::default(),
+            reason: "cpu 95.0% >= 90.0%".to_string(),
+        };
+        assert!(notifier.notify(&event).await.is_err());
+        // Not retried
+        assert_eq!(handle.await.unwrap().len(), 1);
+    }
+
+    fn email_config(port: u16) -> config::Email {
+        config::Email {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "visor <visor@example.com>".to_string(),
+            cc: vec!["ops@example.com".to_string()],
+            fallback: vec!["admin@example.com".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_email_owner() {
+        let (port, handle) = smtp_sink().await;
+        let notifier = EmailNotifier::new(&email_config(port), renderer()).unwrap();
+        notifier
+            .notify(&stopped("owner@example.com"))
+            .await
+            .unwrap();
+
+        let mail = handle.await.unwrap();
+        assert_eq!(mail.mail_from, "visor@example.com");
+        assert_eq!(mail.rcpt_to, vec!["owner@example.com", "ops@example.com"]);
+        assert!(mail.data.contains("Subject: "));
+        assert!(mail.data.contains("Cc: ops@example.com"));
+        assert!(mail.data.contains("multipart/alternative"));
+        assert!(mail.data.contains("text/plain"));
+        assert!(mail.data.contains("text/html"));
+    }
+
+    #[tokio::test]
+    async fn test_email_fallback() {
+        let (port, handle) = smtp_sink().await;
+        let notifier = EmailNotifier::new(&email_config(port), renderer()).unwrap();
+        notifier.notify(&stopped("")).await.unwrap();
+
+        let mail = handle.await.unwrap();
+        assert_eq!(mail.rcpt_to, vec!["admin@example.com", "ops@example.com"]);
+    }
+
+    #[test]
+    fn test_render_removed() {
+        let event = Event::ContainerRemoved {
+            container: ContainerSummary {
+                labels: Some(HashMap::from([(
+                    "visor.owner".to_string(),
+                    "a@b.com".to_string(),
+                )])),
+                ..container()
+            },
+            reason: "exited 604800 seconds ago".to_string(),
+        };
+        let expected = r#"以下已停止的容器已被删除:
+> 创建者: <font color="comment">a@b.com</font>
+> 容器ID: <font color="comment">abc123</font>
+> 容器名称: <font color="comment">project-a</font>
+> 镜像: <font color="comment">ones/project:dev</font>
+> 原因: <font color="comment">exited 604800 seconds ago</font>"#;
+        assert_eq!(
+            renderer().render(&event, Format::Wecom).unwrap().unwrap(),
+            expected
+        );
+
+        let event = Event::ThresholdExceeded {
+            metrics: Sample {
+                cpu: 95.0,
+                mem: 60.0,
+                disk: 70.0,
+            },
+            reason: "cpu 95.0% >= 90.0%".to_string(),
+        };
+        let msg = renderer().render(&event, Format::Plain).unwrap().unwrap();
+        assert!(msg.starts_with("私有部署环境资源使用达到上限"));
+        assert!(msg.contains("\n  原因: cpu 95.0% >= 90.0%\n  CPU: 95%\n"));
+    }
+}
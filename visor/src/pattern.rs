@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use regex::Regex;
+
+const REGEX_PREFIX: &str = "re:";
+
+/// A whitelist entry: `re:<regex>`, a glob containing `*`, `?` or `[`, or an exact value.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn is_match(&self, s: &str) -> bool {
+        match self {
+            Pattern::Glob(p) => p.matches(s),
+            Pattern::Regex(re) => re.is_match(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PatternSet {
+    exact: HashSet<String>,
+    patterns: Vec<Pattern>,
+}
+
+impl PatternSet {
+    pub fn new(entries: &[String]) -> Result<Self> {
+        let mut set = PatternSet::default();
+        for entry in entries.iter() {
+            let entry = normalize(entry);
+            if let Some(expr) = entry.strip_prefix(REGEX_PREFIX) {
+                let re = Regex::new(expr)
+                    .map_err(|e| anyhow!("Invalid whitelist regex {}: {}", entry, e))?;
+                set.patterns.push(Pattern::Regex(re));
+            } else if entry.contains(['*', '?', '[']) {
+                let p = glob::Pattern::new(entry)
+                    .map_err(|e| anyhow!("Invalid whitelist glob {}: {}", entry, e))?;
+                set.patterns.push(Pattern::Glob(p));
+            } else {
+                set.exact.insert(entry.to_string());
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        let s = normalize(s);
+        self.exact.contains(s) || self.patterns.iter().any(|p| p.is_match(s))
+    }
+}
+
+/// Docker reports container names with a leading `/`, which is ignored on both sides.
+fn normalize(s: &str) -> &str {
+    let s = s.trim();
+    if s.starts_with(REGEX_PREFIX) {
+        s
+    } else {
+        s.trim_start_matches('/')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PatternSet;
+
+    #[test]
+    fn test_pattern_set() {
+        let entries: Vec<String> = vec![
+            "registry.local/ones/*:release-*",
+            "/mysql",
+            "ci-runner-*",
+            "re:^sha256:[0-9a-f]{4}$",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        let set = PatternSet::new(&entries).unwrap();
+
+        assert!(set.is_match("registry.local/ones/project:release-3.2"));
+        assert!(!set.is_match("registry.local/ones/project:dev-3.2"));
+        assert!(set.is_match("/ci-runner-12"));
+        assert!(set.is_match("/mysql"));
+        assert!(set.is_match("mysql"));
+        assert!(!set.is_match("mysql-1"));
+        assert!(set.is_match("sha256:ab12"));
+        assert!(!set.is_match("sha256:ab123"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(PatternSet::new(&["re:(".to_string()]).is_err());
+        assert!(PatternSet::new(&["[a-".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    RemoveContainer,
+    StopContainer,
+    RemoveImage,
+    RemoveVolume,
+    RemovePkg,
+    RemoveRelease,
+}
+
+impl ActionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionKind::RemoveContainer => "remove_container",
+            ActionKind::StopContainer => "stop_container",
+            ActionKind::RemoveImage => "remove_image",
+            ActionKind::RemoveVolume => "remove_volume",
+            ActionKind::RemovePkg => "remove_pkg",
+            ActionKind::RemoveRelease => "remove_release",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Action {
+    pub kind: ActionKind,
+    pub target: String,
+    pub reason: String,
+}
+
+/// Collects the destructive actions decided during a `monitor()` run.
+/// In dry-run mode the actions are only recorded, never executed.
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub dry_run: bool,
+    pub actions: Vec<Action>,
+    /// Checks skipped in this run, their actions are missing from `actions`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub not_evaluated: Vec<String>,
+}
+
+impl Plan {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            actions: Vec::new(),
+            not_evaluated: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, kind: ActionKind, target: &str, reason: String) {
+        self.actions.push(Action {
+            kind,
+            target: target.to_string(),
+            reason,
+        });
+    }
+
+    pub fn not_evaluated(&mut self, check: &str) {
+        self.not_evaluated.push(check.to_string());
+    }
+
+    pub fn print(&self) -> Result<()> {
+        println!("{}", self.to_json()?);
+        Ok(())
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_dry_run_plan() {
+        let mut plan = Plan::new(true);
+        plan.record(
+            ActionKind::RemoveImage,
+            "sha256:abc",
+            "created 1 is older than lifecycle 86400 seconds".to_string(),
+        );
+        plan.record(
+            ActionKind::StopContainer,
+            "abc123",
+            "cpu 95.0% >= 90.0%".to_string(),
+        );
+        let value: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "dry_run": true,
+                "actions": [
+                    {
+                        "kind": "remove_image",
+                        "target": "sha256:abc",
+                        "reason": "created 1 is older than lifecycle 86400 seconds"
+                    },
+                    {
+                        "kind": "stop_container",
+                        "target": "abc123",
+                        "reason": "cpu 95.0% >= 90.0%"
+                    }
+                ]
+            })
+        );
+
+        plan.not_evaluated("sustained thresholds");
+        let value: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        assert_eq!(value["not_evaluated"], json!(["sustained thresholds"]));
+        plan.print().unwrap();
+    }
+}
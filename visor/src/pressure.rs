@@ -0,0 +1,275 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Threshold};
+use crate::psutil::Sample;
+
+#[derive(Debug, Clone, Copy)]
+enum Resource {
+    Cpu,
+    Mem,
+}
+
+impl Resource {
+    fn name(&self) -> &'static str {
+        match self {
+            Resource::Cpu => "CPU",
+            Resource::Mem => "MEM",
+        }
+    }
+
+    fn value(&self, sample: &Sample) -> f32 {
+        match self {
+            Resource::Cpu => sample.cpu,
+            Resource::Mem => sample.mem,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    resource: Resource,
+    trigger: f32,
+    recover: f32,
+    duration: Duration,
+    active: bool,
+}
+
+impl Rule {
+    fn new(resource: Resource, threshold: &Threshold) -> Self {
+        Self {
+            resource,
+            trigger: threshold.trigger,
+            recover: threshold.recover.unwrap_or(threshold.trigger),
+            duration: Duration::from_secs(threshold.duration),
+            active: false,
+        }
+    }
+
+    fn evaluate(&mut self, samples: &[(Instant, Sample)], now: Instant) -> Option<String> {
+        let (_, latest) = samples.last()?;
+        let latest = self.resource.value(latest);
+
+        if self.active {
+            if latest < self.recover {
+                self.active = false;
+                return None;
+            }
+            return Some(format!(
+                "{} {}% has not recovered below {}%",
+                self.resource.name(),
+                latest,
+                self.recover
+            ));
+        }
+
+        if self.duration.is_zero() {
+            self.active = latest >= self.trigger;
+        } else {
+            // The window must reach back at least `duration` before the rule can trigger
+            let (oldest, _) = samples[0];
+            let covered = now.saturating_duration_since(oldest) >= self.duration;
+            self.active = covered
+                && samples
+                    .iter()
+                    .filter(|(at, _)| now.saturating_duration_since(*at) <= self.duration)
+                    .all(|(_, s)| self.resource.value(s) >= self.trigger);
+        }
+
+        if self.active {
+            Some(format!(
+                "{} {}% at or above {}% for {}s",
+                self.resource.name(),
+                latest,
+                self.trigger,
+                self.duration.as_secs()
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Tracks sustained threshold rules with hysteresis, and the cooldown between stops.
+#[derive(Debug)]
+pub struct PressureState {
+    rules: Vec<Rule>,
+    cooldown: Duration,
+    last_stop: Option<Instant>,
+}
+
+impl PressureState {
+    pub fn new(cfg: &Config) -> Self {
+        match &cfg.pressure {
+            Some(pressure) => {
+                let mut rules = Vec::new();
+                if let Some(t) = &pressure.cpu {
+                    rules.push(Rule::new(Resource::Cpu, t));
+                }
+                if let Some(t) = &pressure.mem {
+                    rules.push(Rule::new(Resource::Mem, t));
+                }
+                Self {
+                    rules,
+                    cooldown: Duration::from_secs(pressure.cooldown),
+                    last_stop: None,
+                }
+            }
+            None => {
+                let threshold = |limit| Threshold {
+                    trigger: limit,
+                    recover: None,
+                    duration: 0,
+                };
+                Self {
+                    rules: vec![
+                        Rule::new(Resource::Cpu, &threshold(cfg.cpu_limit)),
+                        Rule::new(Resource::Mem, &threshold(cfg.mem_limit)),
+                    ],
+                    cooldown: Duration::ZERO,
+                    last_stop: None,
+                }
+            }
+        }
+    }
+
+    /// Updates every rule with the latest samples and returns the reasons of the active ones.
+    pub fn evaluate(&mut self, samples: &[(Instant, Sample)], now: Instant) -> Option<String> {
+        let reasons: Vec<String> = self
+            .rules
+            .iter_mut()
+            .filter_map(|rule| rule.evaluate(samples, now))
+            .collect();
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join(", "))
+        }
+    }
+
+    /// How much longer the samples have to be collected before the window covers the
+    /// longest sustained rule, `None` once it does.
+    pub fn warm_up(&self, samples: &[(Instant, Sample)], now: Instant) -> Option<Duration> {
+        let longest = self.rules.iter().map(|r| r.duration).max()?;
+        if longest.is_zero() {
+            return None;
+        }
+        let covered = match samples.first() {
+            Some((oldest, _)) => now.saturating_duration_since(*oldest),
+            None => Duration::ZERO,
+        };
+        longest.checked_sub(covered).filter(|d| !d.is_zero())
+    }
+
+    pub fn cooldown_remaining(&self, now: Instant) -> Option<Duration> {
+        let last_stop = self.last_stop?;
+        let elapsed = now.saturating_duration_since(last_stop);
+        if elapsed < self.cooldown {
+            Some(self.cooldown - elapsed)
+        } else {
+            None
+        }
+    }
+
+    pub fn stopped(&mut self, now: Instant) {
+        self.last_stop = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(now: Instant, cpus: &[(u64, f32)]) -> Vec<(Instant, Sample)> {
+        cpus.iter()
+            .map(|(age, cpu)| {
+                let sample = Sample {
+                    cpu: *cpu,
+                    ..Default::default()
+                };
+                (now - Duration::from_secs(*age), sample)
+            })
+            .collect()
+    }
+
+    fn rule(trigger: f32, recover: f32, duration: u64) -> Rule {
+        Rule::new(
+            Resource::Cpu,
+            &Threshold {
+                trigger,
+                recover: Some(recover),
+                duration,
+            },
+        )
+    }
+
+    #[test]
+    fn test_sustained_trigger() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let mut r = rule(90.0, 75.0, 300);
+
+        // A short spike does not trigger
+        let s = samples(now, &[(400, 20.0), (200, 30.0), (10, 99.0), (0, 99.0)]);
+        assert!(r.evaluate(&s, now).is_none());
+
+        // Window too short to cover the duration
+        let s = samples(now, &[(100, 95.0), (0, 95.0)]);
+        assert!(r.evaluate(&s, now).is_none());
+
+        let s = samples(now, &[(400, 20.0), (300, 95.0), (150, 92.0), (0, 96.0)]);
+        assert!(r.evaluate(&s, now).is_some());
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let mut r = rule(90.0, 75.0, 0);
+
+        assert!(r.evaluate(&samples(now, &[(0, 91.0)]), now).is_some());
+        // Between recover and trigger keeps the rule active
+        assert!(r.evaluate(&samples(now, &[(0, 80.0)]), now).is_some());
+        assert!(r.evaluate(&samples(now, &[(0, 70.0)]), now).is_none());
+        assert!(r.evaluate(&samples(now, &[(0, 80.0)]), now).is_none());
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let now = Instant::now() + Duration::from_secs(3600);
+        let state = PressureState {
+            rules: vec![rule(90.0, 75.0, 300), rule(90.0, 80.0, 60)],
+            cooldown: Duration::ZERO,
+            last_stop: None,
+        };
+        assert_eq!(state.warm_up(&[], now), Some(Duration::from_secs(300)));
+        let s = samples(now, &[(100, 95.0), (0, 95.0)]);
+        assert_eq!(state.warm_up(&s, now), Some(Duration::from_secs(200)));
+        let s = samples(now, &[(300, 95.0), (0, 95.0)]);
+        assert!(state.warm_up(&s, now).is_none());
+
+        // Instant rules need a single sample only
+        let state = PressureState {
+            rules: vec![rule(90.0, 90.0, 0)],
+            cooldown: Duration::ZERO,
+            last_stop: None,
+        };
+        assert!(state.warm_up(&[], now).is_none());
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let now = Instant::now();
+        let mut state = PressureState {
+            rules: vec![],
+            cooldown: Duration::from_secs(120),
+            last_stop: None,
+        };
+        assert!(state.cooldown_remaining(now).is_none());
+        state.stopped(now);
+        assert_eq!(
+            state.cooldown_remaining(now + Duration::from_secs(20)),
+            Some(Duration::from_secs(100))
+        );
+        assert!(state
+            .cooldown_remaining(now + Duration::from_secs(120))
+            .is_none());
+    }
+}
@@ -1,15 +1,143 @@
-use std::thread;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use log::warn;
 use psutil::Percent;
 use psutil::{cpu, disk, memory};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::task;
+use tokio::time::{self, MissedTickBehavior};
 
-pub fn get_cpu_usage() -> Result<Percent> {
+use crate::metrics;
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Sample {
+    pub cpu: Percent,
+    pub mem: Percent,
+    pub disk: Percent,
+}
+
+type Window = VecDeque<(Instant, Sample)>;
+
+/// Shared handle to the rolling window kept by the background sampler.
+#[derive(Debug, Clone)]
+pub struct HostMetrics {
+    rx: watch::Receiver<Window>,
+}
+
+/// Spawns a task sampling CPU, memory and disk usage every `interval`,
+/// keeping the last `window` samples.
+pub fn spawn_sampler(interval: Duration, window: usize) -> Result<HostMetrics> {
+    // CPU usage is measured between two calls, the first sample is taken one interval later
     let mut collector = cpu::CpuPercentCollector::new()?;
-    let block_time = Duration::from_secs(10);
-    thread::sleep(block_time);
-    Ok(collector.cpu_percent()?)
+    let (tx, rx) = watch::channel(Window::with_capacity(window));
+
+    tokio::spawn(async move {
+        let mut ticker = time::interval_at(time::Instant::now() + interval, interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut samples = Window::with_capacity(window);
+        loop {
+            ticker.tick().await;
+            // psutil reads /proc and statfs synchronously, keep it off the runtime workers
+            let res = task::spawn_blocking(move || {
+                let res = take_sample(&mut collector);
+                (collector, res)
+            })
+            .await;
+            let res = match res {
+                Ok((c, res)) => {
+                    collector = c;
+                    res
+                }
+                Err(e) => {
+                    warn!("Host metrics sampler stopped: {}", e);
+                    break;
+                }
+            };
+            let sample = match res {
+                Ok(sample) => {
+                    metrics::observe_host(&sample);
+                    sample
+                }
+                Err(e) => {
+                    warn!("Sample host metrics failed: {}", e);
+                    continue;
+                }
+            };
+            push(&mut samples, (Instant::now(), sample), window);
+            if tx.send(samples.clone()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(HostMetrics { rx })
+}
+
+/// Appends the sample, dropping the oldest ones beyond `window`.
+fn push(samples: &mut Window, sample: (Instant, Sample), window: usize) {
+    samples.push_back(sample);
+    while samples.len() > window {
+        samples.pop_front();
+    }
+}
+
+fn take_sample(collector: &mut cpu::CpuPercentCollector) -> Result<Sample> {
+    Ok(Sample {
+        cpu: collector.cpu_percent()?,
+        mem: get_mem_usage()?,
+        disk: get_disk_usage()?,
+    })
+}
+
+impl HostMetrics {
+    pub fn latest(&self) -> Option<Sample> {
+        self.rx.borrow().back().map(|(_, s)| *s)
+    }
+
+    pub fn average(&self) -> Option<Sample> {
+        let samples = self.rx.borrow();
+        if samples.is_empty() {
+            return None;
+        }
+        let n = samples.len() as Percent;
+        let mut avg = Sample::default();
+        for (_, s) in samples.iter() {
+            avg.cpu += s.cpu / n;
+            avg.mem += s.mem / n;
+            avg.disk += s.disk / n;
+        }
+        Some(avg)
+    }
+
+    pub fn samples(&self) -> Vec<(Instant, Sample)> {
+        self.rx.borrow().iter().copied().collect()
+    }
+
+    /// Returns the latest sample, waiting for the first one if none was taken yet.
+    pub async fn current(&self) -> Result<Sample> {
+        match self.latest() {
+            Some(sample) => Ok(sample),
+            None => self.next().await,
+        }
+    }
+
+    /// Waits for a sample taken after this call.
+    pub async fn next(&self) -> Result<Sample> {
+        let start = Instant::now();
+        let mut rx = self.rx.clone();
+        loop {
+            if let Some((at, sample)) = rx.borrow().back() {
+                if at.ge(&start) {
+                    return Ok(*sample);
+                }
+            }
+            rx.changed()
+                .await
+                .map_err(|_| anyhow!("Host metrics sampler stopped"))?;
+        }
+    }
 }
 
 pub fn get_mem_usage() -> Result<Percent> {
@@ -21,3 +149,68 @@ pub fn get_disk_usage() -> Result<Percent> {
     let disk = disk::disk_usage("/")?;
     Ok(disk.percent())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(cpu: Percent) -> Sample {
+        Sample {
+            cpu,
+            mem: cpu / 2.0,
+            disk: 50.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_metrics() {
+        let (tx, rx) = watch::channel(Window::new());
+        let metrics = HostMetrics { rx };
+        assert!(metrics.latest().is_none());
+        assert!(metrics.average().is_none());
+
+        // Only the last 3 samples are kept
+        let mut samples = Window::new();
+        for cpu in [10.0, 20.0, 40.0, 60.0] {
+            push(&mut samples, (Instant::now(), sample(cpu)), 3);
+        }
+        assert_eq!(samples.len(), 3);
+        tx.send(samples.clone()).unwrap();
+        assert_eq!(metrics.samples().len(), 3);
+        assert_eq!(metrics.latest().unwrap().cpu, 60.0);
+        let avg = metrics.average().unwrap();
+        assert!((avg.cpu - 40.0).abs() < 1e-3);
+        assert!((avg.mem - 20.0).abs() < 1e-3);
+        assert!((avg.disk - 50.0).abs() < 1e-3);
+        assert_eq!(metrics.current().await.unwrap().cpu, 60.0);
+
+        // `next` skips the samples taken before the call
+        let next = tokio::spawn({
+            let metrics = metrics.clone();
+            async move { metrics.next().await }
+        });
+        time::sleep(Duration::from_millis(10)).await;
+        tx.send(samples.clone()).unwrap();
+        push(&mut samples, (Instant::now(), sample(80.0)), 3);
+        tx.send(samples).unwrap();
+        assert_eq!(next.await.unwrap().unwrap().cpu, 80.0);
+
+        drop(tx);
+        assert!(metrics.next().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_current_waits() {
+        let (tx, rx) = watch::channel(Window::new());
+        let metrics = HostMetrics { rx };
+        let current = tokio::spawn({
+            let metrics = metrics.clone();
+            async move { metrics.current().await }
+        });
+        time::sleep(Duration::from_millis(10)).await;
+        let mut samples = Window::new();
+        push(&mut samples, (Instant::now(), sample(30.0)), 3);
+        tx.send(samples).unwrap();
+        assert_eq!(current.await.unwrap().unwrap().cpu, 30.0);
+    }
+}
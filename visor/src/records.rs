@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use regex::Regex;
+
+pub const DEFAULT_RECORDS_LOG: &str = "/data/ones/autodeploy/records.log";
+
+/// One deployment written by autodeploy: the deploy dir on its own line,
+/// followed by a line carrying the HTTPS port and usually the deploy time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub deploy_dir: String,
+    pub https_port: i64,
+    pub time: Option<DateTime<Local>>,
+}
+
+/// Records indexed by HTTPS port, later deployments win.
+#[derive(Debug, Default)]
+pub struct RecordsIndex {
+    records: HashMap<i64, Record>,
+}
+
+impl RecordsIndex {
+    pub fn parse(content: &str) -> Self {
+        let mut records = HashMap::new();
+        let time_re = Regex::new(
+            r"\d{4}[-/]\d{2}[-/]\d{2}([ T]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?)?",
+        )
+        .unwrap();
+        let lines: Vec<&str> = content.lines().map(|l| l.trim()).collect();
+        for pair in lines.windows(2) {
+            let (dir, detail) = (pair[0], pair[1]);
+            if !dir.starts_with('/') || detail.starts_with('/') {
+                continue;
+            }
+            let time = parse_time(detail).or_else(|| parse_time(dir));
+            for port in numbers(&time_re.replace_all(detail, " ")) {
+                records.insert(
+                    port,
+                    Record {
+                        deploy_dir: dir.to_string(),
+                        https_port: port,
+                        time,
+                    },
+                );
+            }
+        }
+        Self { records }
+    }
+
+    pub fn get(&self, https_port: i64) -> Option<&Record> {
+        self.records.get(&https_port)
+    }
+}
+
+/// Whole numbers in the line, so `8443` never matches `18443`.
+fn numbers(line: &str) -> Vec<i64> {
+    line.split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty() && s.len() <= 5)
+        .filter_map(|s| s.parse::<i64>().ok())
+        .filter(|p| (1..=65535).contains(p))
+        .collect()
+}
+
+fn parse_time(line: &str) -> Option<DateTime<Local>> {
+    const FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y/%m/%d %H:%M:%S"];
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    for i in 0..tokens.len() {
+        if let Ok(t) = DateTime::parse_from_rfc3339(tokens[i]) {
+            return Some(t.with_timezone(&Local));
+        }
+        if i + 1 < tokens.len() {
+            let s = format!("{} {}", tokens[i], tokens[i + 1]);
+            for fmt in FORMATS.iter() {
+                if let Ok(t) = NaiveDateTime::parse_from_str(&s, fmt) {
+                    return Local.from_local_datetime(&t).single();
+                }
+            }
+        }
+    }
+    None
+}
+
+struct Cache {
+    path: PathBuf,
+    modified: SystemTime,
+    index: RecordsIndex,
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+/// Looks up the deployment record of an HTTPS port, reloading the log when its mtime changes.
+pub fn lookup(path: &str, https_port: i64) -> Result<Option<Record>> {
+    let modified = fs::metadata(path)?.modified()?;
+    let mut cache = CACHE
+        .lock()
+        .map_err(|_| anyhow!("Records cache poisoned"))?;
+
+    let fresh = matches!(&*cache, Some(c) if c.path == Path::new(path) && c.modified == modified);
+    if !fresh {
+        let content = fs::read_to_string(path)?;
+        *cache = Some(Cache {
+            path: PathBuf::from(path),
+            modified,
+            index: RecordsIndex::parse(&content),
+        });
+    }
+    Ok(cache
+        .as_ref()
+        .and_then(|c| c.index.get(https_port))
+        .cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecordsIndex;
+
+    #[test]
+    fn test_parse_records() {
+        let content = r#"
+/data/ones/pkg/project-a
+2022-05-01 10:00:00 https_port: 8443
+/data/ones/pkg/project-b
+2022-05-02 11:00:00 https_port: 18443
+/data/ones/pkg/project-c
+2022-05-03 12:00:00 https_port: 8443
+/data/ones/pkg/project-d
+9443
+"#;
+        let index = RecordsIndex::parse(content);
+
+        let r = index.get(8443).unwrap();
+        assert_eq!(r.deploy_dir, "/data/ones/pkg/project-c");
+        assert_eq!(
+            r.time.unwrap().format("%Y-%m-%d %H:%M:%S").to_string(),
+            "2022-05-03 12:00:00"
+        );
+
+        assert_eq!(
+            index.get(18443).unwrap().deploy_dir,
+            "/data/ones/pkg/project-b"
+        );
+        // A bare port, as matched by `grep -B1 <port>`
+        let r = index.get(9443).unwrap();
+        assert_eq!(r.deploy_dir, "/data/ones/pkg/project-d");
+        assert!(r.time.is_none());
+        assert!(index.get(443).is_none());
+        // Numbers of the timestamp are not taken as ports
+        assert!(index.get(10).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use bollard::Docker;
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::container::container::map_existed_containers;
+use crate::event::Event;
+use crate::instance::find_instance;
+use crate::labels::Policy;
+use crate::lease::Leases;
+use crate::notify::Notifier;
+use crate::plan::Plan;
+use crate::state;
+
+pub const REMINDERS_FILE: &str = "reminders.json";
+
+/// Exit times drift by the time spent inspecting, a larger gap means the container
+/// ran again or its lease was extended.
+const DEADLINE_TOLERANCE: i64 = 60;
+
+/// Reminders already sent, by container ID, kept in `state_dir` across restarts.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SentReminders {
+    containers: HashMap<String, Sent>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Sent {
+    /// Unix time the container is removed, reminders start over when it moves
+    deadline: i64,
+    /// Days before removal already reminded of
+    days: Vec<u64>,
+}
+
+impl SentReminders {
+    /// Returns the reminder due for the container, if any, the closest one when several
+    /// are. Reminders not shorter than the time the container is kept are skipped, the
+    /// stop notification already tells how long that is.
+    fn due(
+        &mut self,
+        container_id: &str,
+        deadline: i64,
+        kept: Duration,
+        remaining: Duration,
+        days: &[u64],
+    ) -> Option<u64> {
+        let sent = self.containers.entry(container_id.to_string()).or_default();
+        if (sent.deadline - deadline).abs() > DEADLINE_TOLERANCE {
+            *sent = Sent {
+                deadline,
+                days: Vec::new(),
+            };
+        }
+
+        days.iter()
+            .copied()
+            .filter(|d| {
+                let before = Duration::from_secs(d * 86400);
+                before < kept && remaining <= before && !sent.days.contains(d)
+            })
+            .min()
+    }
+
+    /// Marks the reminder sent along with the earlier ones it supersedes.
+    fn mark_sent(&mut self, container_id: &str, day: u64, days: &[u64]) {
+        if let Some(sent) = self.containers.get_mut(container_id) {
+            sent.days.extend(days.iter().filter(|d| **d >= day));
+        }
+    }
+
+    fn retain(&mut self, f: impl Fn(&str) -> bool) {
+        self.containers.retain(|id, _| f(id));
+    }
+}
+
+/// Notifies owners whose exited containers are about to be removed by `clean_exited_containers`.
+pub async fn remind_expiring_containers<T>(
+    docker: &Docker,
+    cfg: &Config,
+    notifier: &T,
+    plan: &Plan,
+) -> Result<()>
+where
+    T: Notifier,
+{
+    let days = match &cfg.reminders {
+        Some(reminders) if !reminders.days.is_empty() => &reminders.days,
+        _ => return Ok(()),
+    };
+    if plan.dry_run {
+        info!("Ignored: reminders are not sent in dry run");
+        return Ok(());
+    }
+
+    let path = Path::new(&cfg.state_dir).join(REMINDERS_FILE);
+    let mut sent: SentReminders = state::load(&path)?;
+    let leases = Leases::load(&cfg.state_dir)?;
+    let containers = map_existed_containers(docker).await?.unwrap_or_default();
+    // Forget removed or restarted containers
+    sent.retain(|id| containers.contains_key(id));
+
+    let now = Utc::now().timestamp();
+    for (container_id, container) in containers {
+        let policy = Policy::from_container(&container.container);
+        if policy.protect {
+            continue;
+        }
+        let exited_at = now - container.exist_duration.as_secs() as i64;
+        let lifecycle = policy.lifecycle_or(cfg.lifecycle.container);
+        let deadline = leases.removal_time(&container_id, exited_at, lifecycle, now);
+        if deadline <= now {
+            continue;
+        }
+
+        let kept = Duration::from_secs((deadline - exited_at) as u64);
+        let remaining = Duration::from_secs((deadline - now) as u64);
+        let day = match sent.due(&container_id, deadline, kept, remaining, days) {
+            Some(day) => day,
+            None => continue,
+        };
+
+        // Exited containers no longer publish ports, the owner is found through their bindings
+        let instance = find_instance(docker, &container.container, &cfg.records_log)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Get instance owner failed: {}", e);
+                Default::default()
+            });
+        info!(
+            "Remind container {} will be removed within {} days",
+            container_id, day
+        );
+        let event = Event::ContainerExpiring {
+            container: container.container,
+            instance,
+            remaining,
+        };
+        // Retried on the next round unless sent
+        match notifier.notify(&event).await {
+            Ok(()) => sent.mark_sent(&container_id, day, days),
+            Err(e) => warn!("Send notification failed: {}", e),
+        }
+    }
+    state::save(&path, &sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 86400;
+
+    #[test]
+    fn test_due() {
+        let mut sent = SentReminders::default();
+        let lifecycle = Duration::from_secs(7 * DAY);
+        let days = [3, 1];
+
+        let remaining = Duration::from_secs(5 * DAY);
+        assert_eq!(sent.due("a", 1000, lifecycle, remaining, &days), None);
+        let remaining = Duration::from_secs(3 * DAY);
+        assert_eq!(sent.due("a", 1000, lifecycle, remaining, &days), Some(3));
+        // Due again until the notification is sent
+        assert_eq!(sent.due("a", 1000, lifecycle, remaining, &days), Some(3));
+        sent.mark_sent("a", 3, &days);
+        assert_eq!(sent.due("a", 1010, lifecycle, remaining, &days), None);
+
+        // Both reminders are due after a long downtime, only the closest one is sent
+        let remaining = Duration::from_secs(DAY / 2);
+        assert_eq!(sent.due("b", 1000, lifecycle, remaining, &days), Some(1));
+        sent.mark_sent("b", 1, &days);
+        assert_eq!(sent.due("b", 1000, lifecycle, remaining, &days), None);
+
+        // Exited again after a restart, or the lease was extended
+        assert_eq!(sent.due("b", 9000, lifecycle, remaining, &days), Some(1));
+
+        // Not shorter than the lifecycle
+        let lifecycle = Duration::from_secs(2 * DAY);
+        let remaining = Duration::from_secs(2 * DAY);
+        assert_eq!(sent.due("c", 1000, lifecycle, remaining, &days), None);
+    }
+}
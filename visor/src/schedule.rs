@@ -0,0 +1,260 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bollard::Docker;
+use chrono::Local;
+use log::{info, warn};
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+use crate::config::{Config, Trigger};
+use crate::container::container::*;
+use crate::instance::*;
+use crate::metrics::timed;
+use crate::notify::Notifier;
+use crate::plan::Plan;
+use crate::pressure::PressureState;
+use crate::psutil::HostMetrics;
+use crate::reminder::remind_expiring_containers;
+
+const DEFAULT_RESOURCES_INTERVAL: u64 = 60;
+const DEFAULT_CLEAN_INTERVAL: u64 = 3600;
+
+enum Timer {
+    Interval(Interval),
+    Cron(cron::Schedule),
+}
+
+impl Timer {
+    fn new(trigger: &Option<Trigger>, default_interval: u64) -> Self {
+        if let Some(trigger) = trigger {
+            if let Some(schedule) = &trigger.cron_schedule {
+                return Timer::Cron(schedule.clone());
+            }
+        }
+
+        let secs = trigger
+            .as_ref()
+            .and_then(|t| t.interval)
+            .unwrap_or(default_interval);
+        let mut interval = time::interval(Duration::from_secs(secs));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Timer::Interval(interval)
+    }
+
+    async fn tick(&mut self) {
+        match self {
+            Timer::Interval(interval) => {
+                interval.tick().await;
+            }
+            Timer::Cron(schedule) => {
+                let next = match schedule.upcoming(Local).next() {
+                    Some(next) => next,
+                    None => {
+                        // The expression has no future occurrence, park the task forever
+                        std::future::pending::<()>().await;
+                        return;
+                    }
+                };
+                let d = (next - Local::now()).to_std().unwrap_or_default();
+                time::sleep(d).await;
+            }
+        }
+    }
+}
+
+/// Runs every monitor task on its own timer, as configured in `schedule`.
+/// Each task is spawned separately so a slow one does not hold up the others.
+pub async fn run<T>(cfg: Arc<Config>, docker: Docker, notifier: Arc<T>, metrics: HostMetrics)
+where
+    T: Notifier + 'static,
+{
+    let tasks = vec![
+        (
+            "resources",
+            tokio::spawn(resources(
+                cfg.clone(),
+                docker.clone(),
+                notifier.clone(),
+                metrics,
+            )),
+        ),
+        (
+            "containers",
+            tokio::spawn(containers(cfg.clone(), docker.clone(), notifier.clone())),
+        ),
+        ("dirs", tokio::spawn(dirs(cfg.clone(), docker.clone()))),
+        (
+            "images",
+            tokio::spawn(images(cfg.clone(), docker.clone(), notifier)),
+        ),
+        ("volumes", tokio::spawn(volumes(cfg, docker))),
+    ];
+    for (task, handle) in tasks {
+        if let Err(e) = handle.await {
+            warn!("Scheduled task {} stopped: {}", task, e);
+        }
+    }
+}
+
+async fn resources<T>(cfg: Arc<Config>, docker: Docker, notifier: Arc<T>, metrics: HostMetrics)
+where
+    T: Notifier,
+{
+    let mut timer = Timer::new(&cfg.schedule.resources, DEFAULT_RESOURCES_INTERVAL);
+    let mut pressure = PressureState::new(&cfg);
+    loop {
+        timer.tick().await;
+        info!("Scheduled task: resources");
+        let mut plan = Plan::new(false);
+        // 限制 CPU 和内存使用率，并停止过载的容器
+        let stop = stop_containers(
+            &docker,
+            &cfg,
+            notifier.as_ref(),
+            &metrics,
+            &mut pressure,
+            &mut plan,
+        );
+        if let Err(e) = timed("stop_containers", stop).await {
+            warn!("Stop containers failed: {}", e);
+        }
+    }
+}
+
+async fn containers<T>(cfg: Arc<Config>, docker: Docker, notifier: Arc<T>)
+where
+    T: Notifier,
+{
+    let mut timer = Timer::new(&cfg.schedule.containers, DEFAULT_CLEAN_INTERVAL);
+    loop {
+        timer.tick().await;
+        info!("Scheduled task: containers");
+        let mut plan = Plan::new(false);
+        // 提醒即将被删除的容器
+        let remind = remind_expiring_containers(&docker, &cfg, notifier.as_ref(), &plan);
+        if let Err(e) = timed("remind_containers", remind).await {
+            warn!("Remind containers failed: {}", e);
+        }
+        // 清理停止的容器
+        let clean = clean_exited_containers(&docker, &cfg, notifier.as_ref(), &mut plan);
+        if let Err(e) = timed("clean_containers", clean).await {
+            warn!("Clean containers failed: {}", e);
+        }
+    }
+}
+
+async fn dirs(cfg: Arc<Config>, docker: Docker) {
+    let mut timer = Timer::new(&cfg.schedule.dirs, DEFAULT_CLEAN_INTERVAL);
+    loop {
+        timer.tick().await;
+        info!("Scheduled task: dirs");
+        let mut plan = Plan::new(false);
+        // 清理部署目录
+        let clean = clean_pkg(
+            &docker,
+            cfg.lifecycle.pkg,
+            &cfg.records_log,
+            &cfg.state_dir,
+            &mut plan,
+        );
+        if let Err(e) = timed("clean_pkg", clean).await {
+            warn!("Clean pkg failed: {}", e);
+        }
+        // 清理部署包
+        let clean = clean_release(
+            &docker,
+            cfg.lifecycle.release,
+            &cfg.records_log,
+            &cfg.state_dir,
+            &mut plan,
+        );
+        if let Err(e) = timed("clean_release", clean).await {
+            warn!("Clean release failed: {}", e);
+        }
+    }
+}
+
+async fn images<T>(cfg: Arc<Config>, docker: Docker, notifier: Arc<T>)
+where
+    T: Notifier,
+{
+    let mut timer = Timer::new(&cfg.schedule.images, DEFAULT_CLEAN_INTERVAL);
+    loop {
+        timer.tick().await;
+        info!("Scheduled task: images");
+        let mut plan = Plan::new(false);
+        // 清理镜像
+        let clean = clean_images(&docker, &cfg, notifier.as_ref(), &mut plan);
+        if let Err(e) = timed("clean_images", clean).await {
+            warn!("Clean images failed: {}", e);
+        }
+    }
+}
+
+async fn volumes(cfg: Arc<Config>, docker: Docker) {
+    let mut timer = Timer::new(&cfg.schedule.volumes, DEFAULT_CLEAN_INTERVAL);
+    loop {
+        timer.tick().await;
+        info!("Scheduled task: volumes");
+        let mut plan = Plan::new(false);
+        // 清理数据卷
+        if let Err(e) = timed("clean_volumes", clean_volumes(&docker, &cfg, &mut plan)).await {
+            warn!("Clean volumes failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_interval_timer() {
+        let trigger = Some(Trigger {
+            interval: Some(30),
+            cron: None,
+            cron_schedule: None,
+        });
+        for (trigger, secs) in [(trigger, 30), (None, DEFAULT_CLEAN_INTERVAL)] {
+            let mut timer = Timer::new(&trigger, DEFAULT_CLEAN_INTERVAL);
+            match &timer {
+                Timer::Interval(interval) => {
+                    assert_eq!(interval.period(), Duration::from_secs(secs))
+                }
+                Timer::Cron(_) => panic!("expected an interval"),
+            }
+            // The first tick completes at once
+            time::timeout(Duration::from_millis(100), timer.tick())
+                .await
+                .unwrap();
+            assert!(time::timeout(Duration::from_millis(100), timer.tick())
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cron_timer() {
+        let expr = "* * * * * *";
+        let trigger = Some(Trigger {
+            interval: None,
+            cron: Some(expr.to_string()),
+            cron_schedule: Some(cron::Schedule::from_str(expr).unwrap()),
+        });
+        let mut timer = Timer::new(&trigger, DEFAULT_CLEAN_INTERVAL);
+        assert!(matches!(timer, Timer::Cron(_)));
+        // Every second
+        time::timeout(Duration::from_millis(1500), timer.tick())
+            .await
+            .unwrap();
+
+        // No future occurrence, never fires
+        let expr = "0 0 0 1 1 * 2000";
+        let mut timer = Timer::Cron(cron::Schedule::from_str(expr).unwrap());
+        assert!(time::timeout(Duration::from_millis(100), timer.tick())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+// Incoming webhook 说明 https://api.slack.com/messaging/webhooks
+
+#[derive(Debug, Serialize)]
+pub struct Message {
+    /// Fallback shown in notifications
+    pub text: String,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Section { text: TextObject },
+    Context { elements: Vec<TextObject> },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextObject {
+    Mrkdwn { text: String },
+}
+
+impl Block {
+    pub fn section(text: &str) -> Self {
+        Block::Section {
+            text: TextObject::Mrkdwn {
+                text: text.to_string(),
+            },
+        }
+    }
+}
+
+pub struct IncomingWebhook {
+    webhook: String,
+}
+
+impl IncomingWebhook {
+    pub fn new(webhook: String) -> Result<Self> {
+        if webhook.is_empty() {
+            Err(anyhow!("Webhook is empty"))
+        } else {
+            Ok(Self { webhook })
+        }
+    }
+
+    pub async fn send_message(&self, message: &Message) -> Result<()> {
+        let res = reqwest::Client::new()
+            .post(&self.webhook)
+            .json(message)
+            .send()
+            .await?;
+        // Slack answers `ok` with 200, or an error code such as `invalid_blocks` in the body
+        let status = res.status();
+        let body = res.text().await?;
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!("Failed to send notification: {} {}", status, body))
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const DEFAULT_STATE_DIR: &str = "/var/lib/visor";
+
+/// Reads a JSON state file, a missing file is the default state.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s)
+            .map_err(|e| anyhow!("Parse state {} failed: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(anyhow!("Read state {} failed: {}", path.display(), e)),
+    }
+}
+
+/// Writes through a temporary file, so a crash never leaves a truncated state behind.
+pub fn save<T: Serialize>(path: &Path, state: &T) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    fs::rename(&tmp, path).map_err(|e| anyhow!("Save state {} failed: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_load_save() {
+        let path = std::env::temp_dir().join("visor_state_test/state.json");
+        let _ = fs::remove_file(&path);
+        let state: HashMap<String, u64> = load(&path).unwrap();
+        assert!(state.is_empty());
+
+        let mut state = HashMap::new();
+        state.insert("a".to_string(), 1u64);
+        save(&path, &state).unwrap();
+        assert_eq!(load::<HashMap<String, u64>>(&path).unwrap(), state);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tera::{Context, Tera, Value};
+
+use crate::config;
+use crate::event::EventKind;
+use crate::locale::Locale;
+
+/// Replaced by `zh` or `en` in the template paths of the config.
+const LOCALE_PLACEHOLDER: &str = "{locale}";
+
+/// The template shipped for `kind`, `image_removed` has none.
+fn builtin(kind: EventKind, locale: Locale) -> Option<&'static str> {
+    let s = match (kind, locale) {
+        (EventKind::ContainerStopped, Locale::Zh) => {
+            include_str!("../templates/zh/container_stopped.md")
+        }
+        (EventKind::ContainerStopped, Locale::En) => {
+            include_str!("../templates/en/container_stopped.md")
+        }
+        (EventKind::ContainerExpiring, Locale::Zh) => {
+            include_str!("../templates/zh/container_expiring.md")
+        }
+        (EventKind::ContainerExpiring, Locale::En) => {
+            include_str!("../templates/en/container_expiring.md")
+        }
+        (EventKind::ContainerRemoved, Locale::Zh) => {
+            include_str!("../templates/zh/container_removed.md")
+        }
+        (EventKind::ContainerRemoved, Locale::En) => {
+            include_str!("../templates/en/container_removed.md")
+        }
+        (EventKind::ThresholdExceeded, Locale::Zh) => {
+            include_str!("../templates/zh/threshold_exceeded.md")
+        }
+        (EventKind::ThresholdExceeded, Locale::En) => {
+            include_str!("../templates/en/threshold_exceeded.md")
+        }
+        (EventKind::ImageRemoved, _) => return None,
+    };
+    Some(s)
+}
+
+/// Markup of a notifier. Templates write their lines through the `field`, `link`
+/// and `warning` functions, which every format implements on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// WeCom markdown, values are colored with `<font>`
+    Wecom,
+    /// Standard markdown, for DingTalk and Feishu
+    Markdown,
+    /// Slack mrkdwn
+    Mrkdwn,
+    /// HTML kept in lines, for an email body with `white-space: pre-wrap`
+    Html,
+    Plain,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Wecom,
+        Format::Markdown,
+        Format::Mrkdwn,
+        Format::Html,
+        Format::Plain,
+    ];
+
+    /// `{{ field(label="Owner", value=owner) }}`
+    pub fn field(&self, label: &str, value: &str) -> String {
+        match self {
+            Format::Wecom => format!("> {}: <font color=\"comment\">{}</font>", label, value),
+            Format::Markdown => format!("> {}: {}", label, value),
+            Format::Mrkdwn => format!("> {}: {}", escape_mrkdwn(label), escape_mrkdwn(value)),
+            Format::Html => format!(
+                "  {}: <span style=\"color:#888888\">{}</span>",
+                escape_html(label),
+                escape_html(value)
+            ),
+            Format::Plain => format!("  {}: {}", label, value),
+        }
+    }
+
+    /// `{{ link(label="Keep link", text="Keep it", url=keep_url) }}`, the text defaults to the URL.
+    pub fn link(&self, label: &str, text: &str, url: &str) -> String {
+        match self {
+            Format::Wecom | Format::Markdown => format!("> {}: [{}]({})", label, text, url),
+            Format::Mrkdwn => format!(
+                "> {}: <{}|{}>",
+                escape_mrkdwn(label),
+                url,
+                escape_mrkdwn(text)
+            ),
+            Format::Html => format!(
+                "  {}: <a href=\"{}\">{}</a>",
+                escape_html(label),
+                escape_html(url),
+                escape_html(text)
+            ),
+            Format::Plain if text == url => format!("  {}: {}", label, url),
+            Format::Plain => format!("  {}: {} ({})", label, text, url),
+        }
+    }
+
+    /// `{{ warning(text="Note: ...") }}`
+    pub fn warning(&self, text: &str) -> String {
+        match self {
+            Format::Wecom => format!("<font color=\"warning\">{}</font>", text),
+            Format::Markdown => format!("**{}**", text),
+            Format::Mrkdwn => format!("*{}*", escape_mrkdwn(text)),
+            Format::Html => format!("<span style=\"color:#fa8c16\">{}</span>", escape_html(text)),
+            Format::Plain => text.to_string(),
+        }
+    }
+
+    fn register(self, tera: &mut Tera) {
+        tera.register_function("field", move |args: &HashMap<String, Value>| {
+            Ok(Value::String(
+                self.field(&arg(args, "label")?, &arg(args, "value")?),
+            ))
+        });
+        tera.register_function("link", move |args: &HashMap<String, Value>| {
+            let url = arg(args, "url")?;
+            let text = match args.get("text") {
+                Some(_) => arg(args, "text")?,
+                None => url.clone(),
+            };
+            Ok(Value::String(self.link(&arg(args, "label")?, &text, &url)))
+        });
+        tera.register_function("warning", move |args: &HashMap<String, Value>| {
+            Ok(Value::String(self.warning(&arg(args, "text")?)))
+        });
+    }
+}
+
+/// A string or number argument of a template function.
+fn arg(args: &HashMap<String, Value>, name: &str) -> tera::Result<String> {
+    match args.get(name) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(v @ Value::Number(_)) => Ok(v.to_string()),
+        Some(v) => Err(tera::Error::msg(format!("Invalid {}: {}", name, v))),
+        None => Err(tera::Error::msg(format!("Missing {}", name))),
+    }
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Slack only requires the control characters to be escaped.
+fn escape_mrkdwn(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Variables of the `container_stopped` template.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContainerStopped {
+    pub base_url: String,
+    pub owner: String,
+    /// e.g. `3 天 2 小时` or `3 days 2 hours`
+    pub running_time: String,
+    /// Short ID, 12 characters
+    pub container_id: String,
+    pub container_name: String,
+    pub deploy_dir: String,
+    pub volume: String,
+    pub metrics: Metrics,
+    /// One-click restart link, empty without `serv_url`
+    pub restart_url: String,
+    /// Days before an exited container is removed
+    pub lifecycle: u64,
+    pub reason: String,
+}
+
+/// Usage percent, truncated to integers.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Metrics {
+    pub cpu: i32,
+    pub mem: i32,
+    pub disk: i32,
+}
+
+/// Variables of the `container_expiring` template.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContainerExpiring {
+    pub base_url: String,
+    pub owner: String,
+    /// Short ID, 12 characters
+    pub container_id: String,
+    pub container_name: String,
+    pub deploy_dir: String,
+    /// Time left before removal, e.g. `2 天 23 小时` or `2 days 23 hours`
+    pub remaining: String,
+    /// Link extending the container's lease, empty without `serv_url`
+    pub keep_url: String,
+}
+
+/// Variables of the `container_removed` template.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContainerRemoved {
+    /// Empty without the `visor.owner` label
+    pub owner: String,
+    /// Short ID, 12 characters
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub reason: String,
+}
+
+/// Variables of the `threshold_exceeded` template.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ThresholdExceeded {
+    pub host: String,
+    pub metrics: Metrics,
+    pub reason: String,
+}
+
+/// Tera templates of the notification messages, one per locale, built-in unless
+/// overridden in the config. Each format renders them with its own functions.
+#[derive(Debug)]
+pub struct Templates {
+    formats: HashMap<Format, Tera>,
+}
+
+impl Templates {
+    /// Loads and checks every template against sample variables, so a typo fails at startup.
+    /// A path containing `{locale}` falls back to the built-in template for missing locales.
+    pub fn load(cfg: &config::Templates) -> Result<Self> {
+        let mut tera = Tera::default();
+        for (kind, custom) in [
+            (EventKind::ContainerStopped, &cfg.container_stopped),
+            (EventKind::ContainerExpiring, &cfg.container_expiring),
+            (EventKind::ContainerRemoved, &cfg.container_removed),
+            (EventKind::ThresholdExceeded, &cfg.threshold_exceeded),
+        ] {
+            for locale in Locale::ALL {
+                let name = template_name(kind, locale);
+                let path = custom.as_ref().and_then(|p| {
+                    let path = p.replace(LOCALE_PLACEHOLDER, locale.as_str());
+                    (!p.contains(LOCALE_PLACEHOLDER) || Path::new(&path).exists()).then_some(path)
+                });
+                match path {
+                    Some(path) => tera
+                        .add_template_file(&path, Some(&name))
+                        .map_err(|e| anyhow!("Load template {} failed: {:?}", path, e))?,
+                    None => {
+                        let builtin = builtin(kind, locale)
+                            .ok_or_else(|| anyhow!("No template for {}", kind.as_str()))?;
+                        tera.add_raw_template(&name, builtin)?
+                    }
+                }
+            }
+        }
+
+        let formats = Format::ALL
+            .iter()
+            .map(|format| {
+                let mut tera = tera.clone();
+                format.register(&mut tera);
+                (*format, tera)
+            })
+            .collect();
+        let templates = Self { formats };
+        templates.check::<ContainerStopped>(EventKind::ContainerStopped)?;
+        templates.check::<ContainerExpiring>(EventKind::ContainerExpiring)?;
+        templates.check::<ContainerRemoved>(EventKind::ContainerRemoved)?;
+        templates.check::<ThresholdExceeded>(EventKind::ThresholdExceeded)?;
+        Ok(templates)
+    }
+
+    fn check<T: Serialize + Default>(&self, kind: EventKind) -> Result<()> {
+        for locale in Locale::ALL {
+            for format in Format::ALL {
+                self.render(kind, locale, format, &T::default())
+                    .map_err(|e| anyhow!("Invalid template {}: {}", kind.as_str(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the template of `kind` with the variables of its struct,
+    /// `image_removed` has none.
+    pub fn render<T: Serialize>(
+        &self,
+        kind: EventKind,
+        locale: Locale,
+        format: Format,
+        vars: &T,
+    ) -> Result<String> {
+        let context = Context::from_serialize(vars)?;
+        let s = self.formats[&format]
+            .render(&template_name(kind, locale), &context)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(s.trim_end().to_string())
+    }
+}
+
+fn template_name(kind: EventKind, locale: Locale) -> String {
+    format!("{}/{}", locale.as_str(), kind.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_stopped(templates: &Templates, locale: Locale, vars: &ContainerStopped) -> String {
+        let kind = EventKind::ContainerStopped;
+        templates.render(kind, locale, Format::Wecom, vars).unwrap()
+    }
+
+    #[test]
+    fn test_default_template() {
+        let templates = Templates::load(&config::Templates::default()).unwrap();
+        let mut vars = ContainerStopped {
+            base_url: "https://dev-1:8443".to_string(),
+            owner: "a@b.com".to_string(),
+            running_time: "1 小时 0 分钟".to_string(),
+            container_id: "abc123".to_string(),
+            container_name: "project-a".to_string(),
+            deploy_dir: "/data/ones/pkg/project-a".to_string(),
+            volume: "/data/ones/volumes/project-a".to_string(),
+            metrics: Metrics {
+                cpu: 95,
+                mem: 60,
+                disk: 70,
+            },
+            restart_url: String::new(),
+            lifecycle: 7,
+            reason: "cpu 95.0% >= 90.0%".to_string(),
+        };
+        let expected = r#"由于私有部署环境资源使用达到上限，以下容器已被强制停止:
+> 访问地址: [https://dev-1:8443](https://dev-1:8443)
+> 创建者: <font color="comment">a@b.com</font>
+> 运行时长: <font color="comment">1 小时 0 分钟</font>
+> 容器ID: <font color="comment">abc123</font>
+> 部署目录: <font color="comment">/data/ones/pkg/project-a</font>
+> 数据卷: <font color="comment">/data/ones/volumes/project-a</font>
+
+当前资源使用情况:
+> CPU: <font color="comment">95%</font>
+> 内存: <font color="comment">60%</font>
+> 磁盘: <font color="comment">70%</font>
+
+如需继续使用该实例，可自行重启容器:
+> 重启命令: <font color="comment">docker start abc123</font>
+
+<font color="warning">注意: 实例如不再使用，将在 7 天后被删除！</font>"#;
+        assert_eq!(render_stopped(&templates, Locale::Zh, &vars), expected);
+
+        vars.restart_url = "https://visor/start_container/abc123".to_string();
+        let s = render_stopped(&templates, Locale::Zh, &vars);
+        assert!(s.contains(
+            "docker start abc123</font>\n> 重启链接: [点击启动](https://visor/start_container/abc123)\n\n"
+        ));
+
+        let s = render_stopped(&templates, Locale::En, &vars);
+        assert!(s.starts_with("The following container was stopped"));
+        assert!(s.contains("> Restart link: [Start](https://visor/start_container/abc123)\n\n"));
+    }
+
+    #[test]
+    fn test_formats() {
+        let templates = Templates::load(&config::Templates::default()).unwrap();
+        let vars = ContainerExpiring {
+            owner: "a@b.com".to_string(),
+            container_id: "abc123".to_string(),
+            remaining: "1 day 1 hour".to_string(),
+            keep_url: "https://visor/extend/abc123?lang=en&days=7".to_string(),
+            ..Default::default()
+        };
+        let render = |format| {
+            templates
+                .render(EventKind::ContainerExpiring, Locale::En, format, &vars)
+                .unwrap()
+        };
+
+        let s = render(Format::Markdown);
+        assert!(s.contains("> Owner: a@b.com\n"));
+        assert!(s.contains("> Keep link: [Keep it](https://visor/extend/abc123?lang=en&days=7)\n"));
+        assert!(s.ends_with("\n**Note: the container will be removed in 1 day 1 hour!**"));
+
+        let s = render(Format::Mrkdwn);
+        assert!(s.contains("> Keep link: <https://visor/extend/abc123?lang=en&days=7|Keep it>\n"));
+        assert!(s.ends_with("\n*Note: the container will be removed in 1 day 1 hour!*"));
+
+        let s = render(Format::Html);
+        assert!(s.contains("  Owner: <span style=\"color:#888888\">a@b.com</span>\n"));
+        assert!(s.contains(
+            "  Keep link: <a href=\"https://visor/extend/abc123?lang=en&amp;days=7\">Keep it</a>\n"
+        ));
+
+        let s = render(Format::Plain);
+        assert!(s.starts_with(
+            "The following exited container will be removed soon:\n  Owner: a@b.com\n"
+        ));
+        assert!(s.contains("  Keep link: Keep it (https://visor/extend/abc123?lang=en&days=7)\n"));
+        assert!(s.ends_with("\nNote: the container will be removed in 1 day 1 hour!"));
+
+        assert_eq!(
+            Format::Mrkdwn.field("Reason", "mem <90%> & cpu"),
+            "> Reason: mem &lt;90%&gt; &amp; cpu"
+        );
+        assert_eq!(
+            Format::Plain.link("URL", "https://a.b", "https://a.b"),
+            "  URL: https://a.b"
+        );
+    }
+
+    #[test]
+    fn test_locale_template() {
+        let dir = std::env::temp_dir().join("visor_locale_template");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("en.md"), "Stopped {{ container_id }}").unwrap();
+        let cfg = config::Templates {
+            container_stopped: Some(dir.join("{locale}.md").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let templates = Templates::load(&cfg).unwrap();
+        let vars = ContainerStopped {
+            container_id: "abc123".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            render_stopped(&templates, Locale::En, &vars),
+            "Stopped abc123"
+        );
+        // zh.md is missing, the built-in template is used
+        let s = render_stopped(&templates, Locale::Zh, &vars);
+        assert!(s.starts_with("由于私有部署环境资源使用达到上限"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let path = std::env::temp_dir().join("visor_invalid_template.md");
+        std::fs::write(&path, "{{ unknown_var }}").unwrap();
+        let cfg = config::Templates {
+            container_stopped: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        assert!(Templates::load(&cfg).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// A request captured by the local HTTP stand-in.
+#[derive(Debug)]
+pub struct Request {
+    /// e.g. `POST /robot/send?access_token=xxx HTTP/1.1`
+    pub request_line: String,
+    /// Keys are lowercased
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// Serves a single HTTP request on a random local port, answering with the JSON `response`.
+/// Returns the base URL and a handle resolving to the captured request.
+pub async fn serve_once(response: &'static str) -> (String, JoinHandle<Request>) {
+    let (url, handle) = serve(vec![(200, response)]).await;
+    let handle = tokio::spawn(async move { handle.await.unwrap().remove(0) });
+    (url, handle)
+}
+
+/// Serves one request per `(status, body)` response, in order, one connection each.
+/// Returns the base URL and a handle resolving to the captured requests.
+pub async fn serve(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<Request>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for (status, response) in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            requests.push(read_request(&mut stream).await);
+            let res = format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                if status < 400 { "OK" } else { "Error" },
+                response.len(),
+                response
+            );
+            stream.write_all(res.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}
+
+async fn read_request(stream: &mut TcpStream) -> Request {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_len = loop {
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed before headers");
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_len]).to_string();
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default().to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+    let content_length = headers
+        .get("content-length")
+        .map(|v| v.parse::<usize>().unwrap())
+        .unwrap_or_default();
+    while buf.len() < head_len + content_length {
+        let n = stream.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    Request {
+        request_line,
+        headers,
+        body: String::from_utf8_lossy(&buf[head_len..head_len + content_length]).to_string(),
+    }
+}
+
+/// A mail captured by the local SMTP sink.
+#[derive(Debug)]
+pub struct Mail {
+    pub mail_from: String,
+    pub rcpt_to: Vec<String>,
+    /// Raw message, headers included
+    pub data: String,
+}
+
+/// Accepts a single plaintext SMTP session on a random local port.
+/// Returns the port and a handle resolving to the first mail received.
+pub async fn smtp_sink() -> (u16, JoinHandle<Mail>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let handle = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut mail = Mail {
+            mail_from: String::new(),
+            rcpt_to: Vec::new(),
+            data: String::new(),
+        };
+
+        writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_uppercase();
+            if command.starts_with("EHLO") || command.starts_with("HELO") {
+                writer.write_all(b"250 localhost\r\n").await.unwrap();
+            } else if command.starts_with("MAIL FROM:") {
+                mail.mail_from = address(&line);
+                writer.write_all(b"250 OK\r\n").await.unwrap();
+            } else if command.starts_with("RCPT TO:") {
+                mail.rcpt_to.push(address(&line));
+                writer.write_all(b"250 OK\r\n").await.unwrap();
+            } else if command == "DATA" {
+                writer
+                    .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                    .await
+                    .unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    let line = line.strip_prefix('.').unwrap_or(&line);
+                    mail.data.push_str(line);
+                    mail.data.push('\n');
+                }
+                writer.write_all(b"250 OK\r\n").await.unwrap();
+                break;
+            } else {
+                writer.write_all(b"250 OK\r\n").await.unwrap();
+            }
+        }
+        mail
+    });
+    (port, handle)
+}
+
+fn address(line: &str) -> String {
+    line.split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(addr, _)| addr.to_string())
+        .unwrap_or_default()
+}
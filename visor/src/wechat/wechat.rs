@@ -7,9 +7,9 @@ use serde::Deserialize;
 const BASE_API: &str = "https://qyapi.weixin.qq.com";
 
 #[derive(Debug)]
-pub struct Wechat<'a> {
-    pub corp_id: &'a str,
-    pub app_secret: &'a str,
+pub struct Wechat {
+    pub corp_id: String,
+    pub app_secret: String,
     pub access_token: Option<String>,
     pub expires_time: Option<DateTime<Local>>,
     pub client: reqwest::Client,
@@ -38,10 +38,10 @@ pub struct ListDetailUsersResponse {
     pub userlist: Vec<User>,
 }
 
-impl<'a> Wechat<'a> {
+impl Wechat {
     pub fn new(
-        corp_id: &'a str,
-        app_secret: &'a str,
+        corp_id: &str,
+        app_secret: &str,
         users: HashMap<String, String>,
     ) -> Result<Self> {
         if corp_id.is_empty() || app_secret.is_empty() {
@@ -49,8 +49,8 @@ impl<'a> Wechat<'a> {
         } else {
             let client = reqwest::Client::new();
             Ok(Self {
-                corp_id,
-                app_secret,
+                corp_id: corp_id.to_string(),
+                app_secret: app_secret.to_string(),
                 access_token: None,
                 expires_time: None,
                 client,
@@ -104,7 +104,7 @@ impl<'a> Wechat<'a> {
         let res = self
             .client
             .get(&api)
-            .query(&[("corpid", self.corp_id), ("corpsecret", self.app_secret)])
+            .query(&[("corpid", &self.corp_id), ("corpsecret", &self.app_secret)])
             .send()
             .await?
             .json::<AccessToken>()
@@ -0,0 +1,177 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, Whitelist};
+use crate::pattern::PatternSet;
+use crate::state;
+
+/// Written by the visor-serv API, merged into the whitelist of the config on every run.
+pub const WHITELIST_FILE: &str = "whitelist.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Containers,
+    Images,
+    Volumes,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Containers => "containers",
+            Kind::Images => "images",
+            Kind::Volumes => "volumes",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "containers" => Ok(Kind::Containers),
+            "images" => Ok(Kind::Images),
+            "volumes" => Ok(Kind::Volumes),
+            _ => Err(anyhow!(
+                "unknown kind {}, expected containers, images or volumes",
+                s
+            )),
+        }
+    }
+}
+
+/// Whitelist entries by kind, as kept in `WHITELIST_FILE` for those added at runtime.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Entries {
+    #[serde(default)]
+    pub containers: Vec<String>,
+    #[serde(default)]
+    pub images: Vec<String>,
+    #[serde(default)]
+    pub volumes: Vec<String>,
+}
+
+impl Entries {
+    /// The entries of the config alone.
+    pub fn from_config(whitelist: &Whitelist) -> Self {
+        Self {
+            containers: whitelist.containers.clone().unwrap_or_default(),
+            images: whitelist.images.clone().unwrap_or_default(),
+            volumes: whitelist.volumes.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn load(state_dir: &str) -> Result<Self> {
+        state::load(&Path::new(state_dir).join(WHITELIST_FILE))
+    }
+
+    pub fn save(&self, state_dir: &str) -> Result<()> {
+        state::save(&Path::new(state_dir).join(WHITELIST_FILE), self)
+    }
+
+    pub fn entries(&self, kind: Kind) -> &[String] {
+        match kind {
+            Kind::Containers => &self.containers,
+            Kind::Images => &self.images,
+            Kind::Volumes => &self.volumes,
+        }
+    }
+
+    fn entries_mut(&mut self, kind: Kind) -> &mut Vec<String> {
+        match kind {
+            Kind::Containers => &mut self.containers,
+            Kind::Images => &mut self.images,
+            Kind::Volumes => &mut self.volumes,
+        }
+    }
+
+    /// Fails on an invalid glob or regex, returns false when the entry is already present.
+    pub fn add(&mut self, kind: Kind, entry: &str) -> Result<bool> {
+        PatternSet::new(&[entry.to_string()])?;
+        let entries = self.entries_mut(kind);
+        if entries.iter().any(|e| e == entry) {
+            return Ok(false);
+        }
+        entries.push(entry.to_string());
+        Ok(true)
+    }
+
+    /// Returns false when the entry was not added at runtime.
+    pub fn remove(&mut self, kind: Kind, entry: &str) -> bool {
+        let entries = self.entries_mut(kind);
+        let len = entries.len();
+        entries.retain(|e| e != entry);
+        entries.len() != len
+    }
+
+    /// Adds the entries to those of `whitelist` and compiles the result.
+    pub fn merge(&self, whitelist: &Whitelist) -> Result<Whitelist> {
+        let merge = |config: &Option<Vec<String>>, added: &[String]| {
+            Some(config.iter().flatten().chain(added).cloned().collect())
+        };
+        let mut merged = Whitelist {
+            containers: merge(&whitelist.containers, &self.containers),
+            images: merge(&whitelist.images, &self.images),
+            volumes: merge(&whitelist.volumes, &self.volumes),
+            ..whitelist.clone()
+        };
+        merged.compile()?;
+        Ok(merged)
+    }
+}
+
+/// The whitelist of the config with the entries added at runtime, or the config one alone
+/// when they cannot be loaded.
+pub fn load(cfg: &Config) -> Whitelist {
+    match Entries::load(&cfg.state_dir).and_then(|added| added.merge(&cfg.whitelist)) {
+        Ok(whitelist) => whitelist,
+        Err(e) => {
+            warn!("Load whitelist failed: {}", e);
+            cfg.whitelist.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let mut whitelist: Whitelist = serde_yaml::from_str("containers: [nginx]").unwrap();
+        whitelist.compile().unwrap();
+
+        let mut added = Entries::default();
+        assert!(added.add(Kind::Containers, "ci-*").unwrap());
+        assert!(!added.add(Kind::Containers, "ci-*").unwrap());
+        assert!(added.add(Kind::Images, "re:[").is_err());
+        assert!(added.add(Kind::Volumes, "data").unwrap());
+
+        let merged = added.merge(&whitelist).unwrap();
+        let containers = merged.containers_map.as_ref().unwrap();
+        assert!(containers.is_match("nginx"));
+        assert!(containers.is_match("ci-runner-1"));
+        assert!(merged.volumes_map.as_ref().unwrap().is_match("data"));
+
+        assert!(added.remove(Kind::Containers, "ci-*"));
+        assert!(!added.remove(Kind::Containers, "nginx"));
+        let merged = added.merge(&whitelist).unwrap();
+        assert!(!merged
+            .containers_map
+            .as_ref()
+            .unwrap()
+            .is_match("ci-runner-1"));
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!("images".parse::<Kind>().unwrap(), Kind::Images);
+        assert!("image".parse::<Kind>().is_err());
+    }
+}